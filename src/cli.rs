@@ -0,0 +1,60 @@
+//! Command line entry points of the Condemus binary.
+
+use condemus::{render_track_to_wav, Project, RenderOptions, WavFormat};
+use std::sync::Arc;
+
+const RENDER_USAGE: &str = "usage: condemus render <project> <output.wav> \
+[--track <index>] [--rate <hz>] [--format <int16|int24|float32>]";
+
+fn load_project(path: &str) -> Result<Project, String> {
+    if path.ends_with(".zip") {
+        return Project::load_zip_file(path).map_err(|err| format!("{}: {}", path, err));
+    }
+
+    let file = std::fs::File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+    serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(|err| format!("{}: {}", path, err))
+}
+
+fn parse_format(name: &str) -> Result<WavFormat, String> {
+    match name {
+        "int16" | "16" => Ok(WavFormat::Int16),
+        "int24" | "24" => Ok(WavFormat::Int24),
+        "float32" | "float" | "32f" => Ok(WavFormat::Float32),
+        other => Err(format!("unknown WAV format '{}'", other)),
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} needs a value", flag))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: '{}'", flag, value))
+}
+
+/// Renders a track of a project into a WAV file.
+pub fn render(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut positional: Vec<String> = vec![];
+    let mut track: usize = 0;
+    let mut options = RenderOptions::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--track" => track = parse_value(&arg, args.next())?,
+            "--rate" => options.rate = parse_value(&arg, args.next())?,
+            "--format" => {
+                options.format = parse_format(&args.next().ok_or("--format needs a value")?)?
+            }
+            _ if arg.starts_with("--") => {
+                return Err(format!("unknown option '{}'\n{}", arg, RENDER_USAGE))
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let [project, output] =
+        <[String; 2]>::try_from(positional).map_err(|_| RENDER_USAGE.to_owned())?;
+
+    let project = Arc::new(load_project(&project)?);
+    render_track_to_wav(project, track, &output, &options).map_err(|err| err.to_string())
+}
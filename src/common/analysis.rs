@@ -0,0 +1,142 @@
+//! Analysis of sample audio, for suggesting edits.
+
+use crate::common::*;
+
+/// How [`Sample::find_loop_points`] searches for loop points.
+#[derive(Clone, Copy, Debug)]
+pub struct LoopSearch {
+    /// How far from the rough loop points to look, in seconds.
+    pub radius: f64,
+    /// Length of the audio compared around the loop points, in seconds.
+    pub window: f64,
+    /// Whether to only consider rising zero crossings, when there are any in reach.
+    pub zero_crossings: bool,
+    /// How many candidates to suggest at most.
+    pub candidates: usize,
+}
+
+impl Default for LoopSearch {
+    fn default() -> Self {
+        Self {
+            radius: 0.01,
+            window: 0.005,
+            zero_crossings: true,
+            candidates: 5,
+        }
+    }
+}
+
+/// A suggested loop, and how badly its end lines up with its start.
+#[derive(Clone, Copy, Debug)]
+pub struct LoopCandidate {
+    pub section: LoopSection,
+    /// Difference between the audio around the end and around the start, relative to their
+    /// energy: 0 for identical waveforms, 1 for uncorrelated ones and 2 for opposite ones.
+    pub mismatch: f64,
+}
+
+/// Frames near `around` to try as a loop point.
+fn loop_point_candidates(
+    audio: &[f64],
+    around: usize,
+    radius: usize,
+    zero_crossings: bool,
+) -> Vec<usize> {
+    let from = around.saturating_sub(radius).max(1);
+    let to = (around + radius).min(audio.len().saturating_sub(1));
+    let frames = from..=to;
+
+    if zero_crossings {
+        let crossings: Vec<usize> = frames
+            .clone()
+            .filter(|i| audio[i - 1] < 0.0 && audio[*i] >= 0.0)
+            .collect();
+
+        if !crossings.is_empty() {
+            return crossings;
+        }
+    }
+
+    frames.collect()
+}
+
+/// How badly the audio around two frames lines up, as described on [`LoopCandidate`].
+fn mismatch(audio: &[f64], a: usize, b: usize, window: usize) -> f64 {
+    let frame = |i: isize| {
+        if i < 0 {
+            0.0
+        } else {
+            audio.get(i as usize).copied().unwrap_or(0.0)
+        }
+    };
+
+    let half = (window / 2) as isize;
+    let mut difference = 0.0;
+    let mut energy = 0.0;
+
+    for k in -half..=half {
+        let x = frame(a as isize + k);
+        let y = frame(b as isize + k);
+
+        difference += (x - y) * (x - y);
+        energy += x * x + y * y;
+    }
+
+    if energy == 0.0 {
+        0.0
+    } else {
+        difference / energy
+    }
+}
+
+impl Sample {
+    /// All channels, averaged into one, for analysis.
+    fn analysis_channel(&self) -> Vec<f64> {
+        let channels: Vec<_> = (0..self.channels).map(|c| self.channel(c)).collect();
+
+        (0..self.frames())
+            .map(|i| {
+                channels.iter().map(|c| c.frame(i as isize)).sum::<f64>() / channels.len() as f64
+            })
+            .collect()
+    }
+
+    /// Suggests loops close to a rough one, best first.
+    ///
+    /// Loop points are moved to wherever the audio leading into the end of the loop continues
+    /// most like the audio at its start, so that jumping back sounds seamless. The crossfade of
+    /// the rough loop is kept, along with its repeats.
+    pub fn find_loop_points(&self, rough: LoopSection, search: &LoopSearch) -> Vec<LoopCandidate> {
+        let audio = self.analysis_channel();
+
+        if audio.len() < 2 {
+            return vec![];
+        }
+
+        let frames = |secs: f64| (secs * self.baserate).round().max(0.0) as usize;
+        let radius = frames(search.radius);
+        let window = frames(search.window).max(1);
+
+        let starts =
+            loop_point_candidates(&audio, frames(rough.from), radius, search.zero_crossings);
+        let ends = loop_point_candidates(&audio, frames(rough.to), radius, search.zero_crossings);
+
+        let mut candidates: Vec<LoopCandidate> = starts
+            .iter()
+            .flat_map(|from| ends.iter().map(move |to| (*from, *to)))
+            .filter(|(from, to)| from < to)
+            .map(|(from, to)| LoopCandidate {
+                section: LoopSection {
+                    from: from as f64 / self.baserate,
+                    to: to as f64 / self.baserate,
+                    ..rough
+                },
+                mismatch: mismatch(&audio, from, to, window),
+            })
+            .collect();
+
+        candidates.sort_by(|a, b| a.mismatch.total_cmp(&b.mismatch));
+        candidates.truncate(search.candidates);
+        candidates
+    }
+}
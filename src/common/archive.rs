@@ -0,0 +1,371 @@
+//! Saving and loading projects as ZIP archives.
+//!
+//! An archive holds a JSON manifest with every definition of the project except for sample
+//! audio, which is stored in one entry per sample as raw little-endian values of the sample's
+//! format (24-bit values taking three bytes), laid out as the sample's channels are.
+
+use crate::common::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+
+const MANIFEST_ENTRY: &str = "project.json";
+const FORMAT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct SampleEntry {
+    audio: String,
+    baserate: f64,
+    #[serde(default = "super::sample::one_channel")]
+    channels: usize,
+    #[serde(default)]
+    layout: ChannelLayout,
+    #[serde(default)]
+    format: SampleFormat,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    patterns: Vec<Pattern>,
+    instruments: Vec<Instrument>,
+    tracks: Vec<Track>,
+    samples: Vec<SampleEntry>,
+}
+
+#[derive(Debug)]
+pub enum ProjectFileError {
+    Io(io::Error),
+    Zip(zip::result::ZipError),
+    MissingEntry(String),
+    CorruptEntry { entry: String, reason: String },
+}
+
+impl ProjectFileError {
+    fn corrupt(entry: &str, reason: impl ToString) -> Self {
+        Self::CorruptEntry {
+            entry: entry.to_owned(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ProjectFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+            Self::Zip(err) => write!(f, "invalid project archive: {}", err),
+            Self::MissingEntry(entry) => write!(f, "project archive is missing '{}'", entry),
+            Self::CorruptEntry { entry, reason } => {
+                write!(
+                    f,
+                    "corrupt entry '{}' in project archive: {}",
+                    entry, reason
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProjectFileError {}
+
+impl From<io::Error> for ProjectFileError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<zip::result::ZipError> for ProjectFileError {
+    fn from(err: zip::result::ZipError) -> Self {
+        Self::Zip(err)
+    }
+}
+
+fn sample_entry_name(index: usize) -> String {
+    format!("samples/{}.raw", index)
+}
+
+/// The audio of a sample entry as streamed from the archive, or None if it is too short to be
+/// worth it or cannot be streamed.
+fn stream_entry<R: Read + Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    entry: &SampleEntry,
+    path: &Path,
+    min_duration: f64,
+) -> Result<Option<SampleData>, ProjectFileError> {
+    let file = match archive.by_name(&entry.audio) {
+        Ok(file) => file,
+        Err(zip::result::ZipError::FileNotFound) => {
+            return Err(ProjectFileError::MissingEntry(entry.audio.clone()))
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let size = entry.format.size() as u64;
+
+    if file.compression() != zip::CompressionMethod::Stored || !file.size().is_multiple_of(size) {
+        return Ok(None);
+    }
+
+    let len = (file.size() / size) as usize;
+    let duration = len as f64 / entry.channels.max(1) as f64 / entry.baserate;
+
+    if duration < min_duration {
+        return Ok(None);
+    }
+
+    Ok(Some(SampleData::Streamed(StreamedAudio::new(
+        StreamSource {
+            path: path.to_owned(),
+            offset: file.data_start(),
+            len,
+            format: entry.format,
+        },
+    ))))
+}
+
+fn read_entry<R: Read + Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    name: &str,
+) -> Result<Vec<u8>, ProjectFileError> {
+    let mut file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(zip::result::ZipError::FileNotFound) => {
+            return Err(ProjectFileError::MissingEntry(name.to_owned()))
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut bytes = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut bytes)
+        .map_err(|err| ProjectFileError::corrupt(name, err))?;
+    Ok(bytes)
+}
+
+fn decode_audio(
+    name: &str,
+    bytes: &[u8],
+    format: SampleFormat,
+) -> Result<SampleData, ProjectFileError> {
+    SampleData::from_le_bytes(bytes, format).ok_or_else(|| {
+        ProjectFileError::corrupt(
+            name,
+            format!(
+                "length of {} bytes is not a whole number of frames",
+                bytes.len()
+            ),
+        )
+    })
+}
+
+impl Manifest {
+    /// Checks that every index in the manifest points at something that exists.
+    fn validate(&self) -> Result<(), ProjectFileError> {
+        let corrupt = |reason: String| Err(ProjectFileError::corrupt(MANIFEST_ENTRY, reason));
+
+        if self.version > FORMAT_VERSION {
+            return corrupt(format!("unsupported format version {}", self.version));
+        }
+
+        for (i, instrument) in self.instruments.iter().enumerate() {
+            if instrument.sample >= self.samples.len() {
+                return corrupt(format!(
+                    "instrument #{} uses missing sample #{}",
+                    i, instrument.sample
+                ));
+            }
+
+            for zone in &instrument.zones {
+                if zone.sample >= self.samples.len() {
+                    return corrupt(format!(
+                        "a zone of instrument #{} uses missing sample #{}",
+                        i, zone.sample
+                    ));
+                }
+            }
+        }
+
+        for (i, pattern) in self.patterns.iter().enumerate() {
+            if pattern.instructions.len() != pattern.width as usize * pattern.height as usize {
+                return corrupt(format!(
+                    "pattern #{} has {} instructions, expected {}x{}",
+                    i,
+                    pattern.instructions.len(),
+                    pattern.width,
+                    pattern.height
+                ));
+            }
+
+            if pattern.row_speed.is_nan() || pattern.row_speed <= 0.0 {
+                return corrupt(format!(
+                    "pattern #{} has a row speed of {}",
+                    i, pattern.row_speed
+                ));
+            }
+
+            for instruction in &pattern.instructions {
+                if let Instruction::Note(note) = instruction {
+                    if note.instrument >= self.instruments.len() {
+                        return corrupt(format!(
+                            "pattern #{} uses missing instrument #{}",
+                            i, note.instrument
+                        ));
+                    }
+                }
+            }
+        }
+
+        for (i, track) in self.tracks.iter().enumerate() {
+            for pref in &track.pattern_refs {
+                if pref.pattern >= self.patterns.len() {
+                    return corrupt(format!(
+                        "track #{} uses missing pattern #{}",
+                        i, pref.pattern
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Project {
+    pub fn save_zip<W: Write + Seek>(&self, writer: W) -> Result<(), ProjectFileError> {
+        let mut zip = zip::ZipWriter::new(writer);
+        let options = zip::write::FileOptions::default();
+        // audio barely compresses, and stored entries can be streamed straight from the archive
+        let audio_options = options
+            .compression_method(zip::CompressionMethod::Stored)
+            .large_file(true);
+
+        let manifest = Manifest {
+            version: FORMAT_VERSION,
+            patterns: self.patterns.clone(),
+            instruments: self.instruments.clone(),
+            tracks: self.tracks.clone(),
+            samples: (0..self.samples.len())
+                .map(|i| SampleEntry {
+                    audio: sample_entry_name(i),
+                    baserate: self.samples[i].baserate,
+                    channels: self.samples[i].channels,
+                    layout: self.samples[i].layout,
+                    format: self.samples[i].audio.format(),
+                })
+                .collect(),
+        };
+
+        zip.start_file(MANIFEST_ENTRY, options)?;
+        serde_json::to_writer_pretty(&mut zip, &manifest).map_err(io::Error::from)?;
+
+        for (entry, sample) in manifest.samples.iter().zip(&self.samples) {
+            zip.start_file(entry.audio.as_str(), audio_options)?;
+
+            match &sample.audio {
+                SampleData::Streamed(audio) => audio.copy_to(&mut zip)?,
+                audio => zip.write_all(&audio.to_le_bytes())?,
+            }
+        }
+
+        zip.finish()?.flush()?;
+        Ok(())
+    }
+
+    pub fn load_zip<R: Read + Seek>(reader: R) -> Result<Self, ProjectFileError> {
+        Self::load_zip_streaming(reader, None)
+    }
+
+    /// Loads a project, streaming the audio of samples at least `min_duration` seconds long from
+    /// the archive at `path` instead of loading it.
+    ///
+    /// Only entries stored without compression can be streamed, which is how they are saved.
+    pub fn load_zip_file_streamed<P: AsRef<Path>>(
+        path: P,
+        min_duration: f64,
+    ) -> Result<Self, ProjectFileError> {
+        let reader = io::BufReader::new(std::fs::File::open(&path)?);
+        Self::load_zip_streaming(reader, Some((path.as_ref(), min_duration)))
+    }
+
+    fn load_zip_streaming<R: Read + Seek>(
+        reader: R,
+        streamed: Option<(&Path, f64)>,
+    ) -> Result<Self, ProjectFileError> {
+        let mut archive = zip::ZipArchive::new(reader)?;
+
+        let manifest: Manifest = serde_json::from_slice(&read_entry(&mut archive, MANIFEST_ENTRY)?)
+            .map_err(|err| ProjectFileError::corrupt(MANIFEST_ENTRY, err))?;
+        manifest.validate()?;
+
+        let mut samples = Vec::with_capacity(manifest.samples.len());
+
+        for entry in &manifest.samples {
+            let audio = match streamed {
+                Some((path, min_duration)) => {
+                    stream_entry(&mut archive, entry, path, min_duration)?
+                }
+                None => None,
+            };
+
+            let audio = match audio {
+                Some(audio) => audio,
+                None => {
+                    let bytes = read_entry(&mut archive, &entry.audio)?;
+                    decode_audio(&entry.audio, &bytes, entry.format)?
+                }
+            };
+
+            if entry.channels == 0 || audio.len() % entry.channels != 0 {
+                return Err(ProjectFileError::corrupt(
+                    &entry.audio,
+                    format!(
+                        "length is not a whole amount of {} channel frames",
+                        entry.channels
+                    ),
+                ));
+            }
+
+            samples.push(Sample {
+                audio,
+                baserate: entry.baserate,
+                channels: entry.channels,
+                layout: entry.layout,
+            });
+        }
+
+        Ok(Self {
+            patterns: manifest.patterns,
+            samples,
+            instruments: manifest.instruments,
+            tracks: manifest.tracks,
+        })
+    }
+
+    /// Saves the project into a temporary file next to `path`, which then replaces it.
+    ///
+    /// The file at `path` is left alone if saving fails, and samples streamed from it can be
+    /// saved back into it.
+    pub fn save_zip_file<P: AsRef<Path>>(&self, path: P) -> Result<(), ProjectFileError> {
+        let path = path.as_ref();
+        let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+
+        let saved = std::fs::File::create(&temp_path)
+            .map_err(ProjectFileError::from)
+            .and_then(|file| self.save_zip(io::BufWriter::new(file)));
+
+        match saved {
+            Ok(()) => Ok(std::fs::rename(&temp_path, path)?),
+            Err(err) => {
+                let _ = std::fs::remove_file(&temp_path);
+                Err(err)
+            }
+        }
+    }
+
+    pub fn load_zip_file<P: AsRef<Path>>(path: P) -> Result<Self, ProjectFileError> {
+        Self::load_zip(io::BufReader::new(std::fs::File::open(path)?))
+    }
+}
@@ -0,0 +1,244 @@
+//! Audio buffers and the slices the renderer mixes into.
+
+use crate::common::AudioChannel;
+
+/// Reads a channel of a sample's audio at fractional frame positions.
+///
+/// Positions outside of the audio read as silence.
+pub trait Resampler {
+    fn interpolate(&self, audio: AudioChannel<'_>, at: f64) -> f64;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NearestResampler;
+
+impl Resampler for NearestResampler {
+    fn interpolate(&self, audio: AudioChannel<'_>, at: f64) -> f64 {
+        audio.frame(at.round() as isize)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LinearResampler;
+
+impl Resampler for LinearResampler {
+    fn interpolate(&self, audio: AudioChannel<'_>, at: f64) -> f64 {
+        let floor = at.floor();
+        let frac = at - floor;
+        let floor = floor as isize;
+
+        let a = audio.frame(floor);
+        let b = audio.frame(floor + 1);
+
+        a + (b - a) * frac
+    }
+}
+
+/// Four-point Hermite interpolation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CubicResampler;
+
+impl Resampler for CubicResampler {
+    fn interpolate(&self, audio: AudioChannel<'_>, at: f64) -> f64 {
+        let floor = at.floor();
+        let t = at - floor;
+        let floor = floor as isize;
+
+        let y0 = audio.frame(floor - 1);
+        let y1 = audio.frame(floor);
+        let y2 = audio.frame(floor + 1);
+        let y3 = audio.frame(floor + 2);
+
+        let c0 = y1;
+        let c1 = 0.5 * (y2 - y0);
+        let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+        let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+
+        ((c3 * t + c2) * t + c1) * t + c0
+    }
+}
+
+/// Hands `mix` the channel to read `len` frames from, `step` frames apart from the frame
+/// `start`, along with where to start in it.
+///
+/// Streamed audio has all of the frames that are read copied out at once, so that its cache is
+/// locked once rather than for every frame.
+fn with_frames(
+    audio: AudioChannel<'_>,
+    start: f64,
+    step: f64,
+    len: usize,
+    mix: impl FnOnce(AudioChannel<'_>, f64),
+) {
+    if !audio.is_streamed() || len == 0 {
+        return mix(audio, start);
+    }
+
+    // resamplers read up to two frames on either side of a position
+    let end = start + step * (len - 1) as f64;
+    let window = audio.window(
+        start.min(end).floor() as isize - 2,
+        start.max(end).ceil() as isize + 3,
+    );
+
+    mix(window.channel(), start - window.start() as f64)
+}
+
+/// A mono window of output audio.
+///
+/// `rate` is the amount of output frames per second of playback time. Rendering code that plays
+/// audio at a different speed (e.g. a pitched channel) scales it before handing the slice down.
+pub struct AudioBufferSlice<'a> {
+    pub out: &'a mut [f64],
+    pub rate: f64,
+    pub resampler: &'a dyn Resampler,
+}
+
+impl<'a> AudioBufferSlice<'a> {
+    pub fn new(out: &'a mut [f64], rate: f64, resampler: &'a dyn Resampler) -> Self {
+        Self {
+            out,
+            rate,
+            resampler,
+        }
+    }
+
+    /// Length in frames.
+    pub fn len(&self) -> usize {
+        self.out.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.out.is_empty()
+    }
+
+    /// Length in seconds of playback time.
+    pub fn len_secs(&self) -> f64 {
+        self.out.len() as f64 / self.rate
+    }
+
+    /// The frame at which a point in time, relative to the start of the slice, falls.
+    pub fn frame_at(&self, secs: f64) -> usize {
+        ((secs * self.rate).round().max(0.0) as usize).min(self.out.len())
+    }
+
+    /// Reborrows this slice, so it can be passed on by value and used again afterwards.
+    pub fn reborrow(&mut self) -> AudioBufferSlice<'_> {
+        AudioBufferSlice {
+            out: self.out,
+            rate: self.rate,
+            resampler: self.resampler,
+        }
+    }
+
+    /// Reborrows this slice with a different playback rate.
+    pub fn with_rate(&mut self, rate: f64) -> AudioBufferSlice<'_> {
+        AudioBufferSlice {
+            out: self.out,
+            rate,
+            resampler: self.resampler,
+        }
+    }
+
+    /// A window over the frames in `from..to`.
+    pub fn window(&mut self, from: usize, to: usize) -> AudioBufferSlice<'_> {
+        AudioBufferSlice {
+            out: &mut self.out[from..to],
+            rate: self.rate,
+            resampler: self.resampler,
+        }
+    }
+
+    /// A window over the frames from `from` until the end of the slice.
+    pub fn window_from(&mut self, from: usize) -> AudioBufferSlice<'_> {
+        let to = self.out.len();
+        self.window(from, to)
+    }
+
+    /// Mixes a channel of audio into this slice, scaled by `gain`.
+    ///
+    /// `start` is the position, in seconds, of `audio` that the first frame of the slice reads
+    /// from. `speed` is how many seconds of `audio` go by for each second of playback time;
+    /// negative speeds read backwards.
+    pub fn mix(
+        &mut self,
+        audio: AudioChannel<'_>,
+        baserate: f64,
+        start: f64,
+        speed: f64,
+        gain: f64,
+    ) {
+        let step = speed * baserate / self.rate;
+        let resampler = self.resampler;
+        let out = &mut *self.out;
+
+        with_frames(audio, start * baserate, step, out.len(), |audio, start| {
+            for (i, out) in out.iter_mut().enumerate() {
+                *out += resampler.interpolate(audio, start + step * i as f64) * gain;
+            }
+        });
+    }
+
+    /// Like [`Self::mix`], but scales each frame by `shape`, which receives the frame's time in
+    /// seconds of playback since the start of the slice.
+    pub fn mix_shaped<F: FnMut(f64) -> f64>(
+        &mut self,
+        audio: AudioChannel<'_>,
+        baserate: f64,
+        start: f64,
+        speed: f64,
+        mut shape: F,
+    ) {
+        let step = speed * baserate / self.rate;
+        let (resampler, rate) = (self.resampler, self.rate);
+        let out = &mut *self.out;
+
+        with_frames(audio, start * baserate, step, out.len(), |audio, start| {
+            for (i, out) in out.iter_mut().enumerate() {
+                let gain = shape(i as f64 / rate);
+
+                if gain != 0.0 {
+                    *out += resampler.interpolate(audio, start + step * i as f64) * gain;
+                }
+            }
+        });
+    }
+
+    /// Mixes another slice's frames into this one, scaled by `gain`.
+    pub fn add_scaled(&mut self, other: &[f64], gain: f64) {
+        for (out, x) in self.out.iter_mut().zip(other) {
+            *out += x * gain;
+        }
+    }
+}
+
+/// An owned buffer of output audio.
+#[derive(Clone, Debug)]
+pub struct AudioBuffer {
+    pub data: Vec<f64>,
+    pub rate: f64,
+}
+
+impl AudioBuffer {
+    pub fn new(len: usize, rate: f64) -> Self {
+        Self {
+            data: vec![0.0; len],
+            rate,
+        }
+    }
+
+    /// Resizes the buffer to `len` frames and silences it.
+    pub fn clear(&mut self, len: usize) {
+        self.data.clear();
+        self.data.resize(len, 0.0);
+    }
+
+    pub fn slice<'a>(&'a mut self, resampler: &'a dyn Resampler) -> AudioBufferSlice<'a> {
+        AudioBufferSlice::new(&mut self.data, self.rate, resampler)
+    }
+}
+
+/// Anything that renders into a pair of left and right output slices.
+pub trait StereoSource {
+    fn render<'a>(&mut self, left_sink: AudioBufferSlice<'a>, right_sink: AudioBufferSlice<'a>);
+}
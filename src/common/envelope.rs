@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct EnvelopePoint {
+    pub time: f64,
+    pub value: f64,
+}
+
+/// A section of an envelope between two of its points, by index.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct EnvelopeLoop {
+    pub from: usize,
+    pub to: usize,
+}
+
+/// A breakpoint envelope, linearly interpolated between points.
+///
+/// While a note is held, the sustain loop repeats (or holds, if both of its ends are the same
+/// point); once it is released, the envelope plays on past it. The regular loop repeats
+/// regardless of release.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Envelope {
+    pub points: Vec<EnvelopePoint>,
+    #[serde(default)]
+    pub sustain: Option<EnvelopeLoop>,
+    #[serde(default)]
+    pub looping: Option<EnvelopeLoop>,
+}
+
+impl Envelope {
+    /// A classic attack-decay-sustain-release envelope, in seconds and from a peak of 1.
+    pub fn adsr(attack: f64, decay: f64, sustain: f64, release: f64) -> Self {
+        let point = |time, value| EnvelopePoint { time, value };
+
+        Self {
+            points: vec![
+                point(0.0, 0.0),
+                point(attack, 1.0),
+                point(attack + decay, sustain),
+                point(attack + decay + release, 0.0),
+            ],
+            sustain: Some(EnvelopeLoop { from: 2, to: 2 }),
+            looping: None,
+        }
+    }
+
+    /// The value of the envelope at a point in time, or None if it has no points.
+    pub fn value_at(&self, time: f64) -> Option<f64> {
+        let next = self.points.partition_point(|point| point.time <= time);
+
+        if next == 0 {
+            return self.points.first().map(|point| point.value);
+        }
+
+        let prev = self.points[next - 1];
+
+        Some(match self.points.get(next) {
+            Some(next) if next.time > prev.time => {
+                prev.value
+                    + (next.value - prev.value) * (time - prev.time) / (next.time - prev.time)
+            }
+            _ => prev.value,
+        })
+    }
+
+    /// The loop that is in effect, depending on whether the note was released.
+    pub fn active_loop(&self, released: bool) -> Option<EnvelopeLoop> {
+        if released {
+            self.looping
+        } else {
+            self.sustain.or(self.looping)
+        }
+    }
+
+    pub fn len(&self) -> f64 {
+        self.points.last().map_or(0.0, |point| point.time)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
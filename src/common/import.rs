@@ -0,0 +1,275 @@
+//! Importing samples from audio files.
+
+use crate::common::*;
+use std::fmt;
+use std::path::Path;
+
+pub use creak::AudioFormat;
+
+/// How the channels of a multi-channel file are folded into a mono sample.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Downmix {
+    /// Average all channels.
+    Average,
+
+    /// Sum all channels, which keeps the level of content present on a single channel. Integer
+    /// audio is clipped to its range.
+    Sum,
+
+    /// Keep only one channel, by index.
+    Channel(usize),
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImportOptions {
+    /// How to fold the file into a mono sample, if at all. Without one, the sample keeps all the
+    /// channels of the file, interleaved.
+    pub downmix: Option<Downmix>,
+}
+
+/// A sample decoded from a file, along with what is known about its source.
+pub struct ImportedSample {
+    pub sample: Sample,
+    pub format: AudioFormat,
+    pub channels: usize,
+    pub duration: f64,
+    /// The bit depth the file stores its audio at, for formats that have one.
+    pub bits_per_sample: Option<u32>,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Decode(creak::DecoderError),
+    NoChannels,
+    NoSuchChannel { channel: usize, channels: usize },
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Decode(err) => write!(f, "could not decode audio: {}", err),
+            Self::NoChannels => write!(f, "audio has no channels"),
+            Self::NoSuchChannel { channel, channels } => write!(
+                f,
+                "cannot keep channel #{} of audio with {} channels",
+                channel, channels
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<creak::DecoderError> for ImportError {
+    fn from(err: creak::DecoderError) -> Self {
+        Self::Decode(err)
+    }
+}
+
+// errors of the decoders read directly are reported like creak reports them
+
+impl From<hound::Error> for ImportError {
+    fn from(err: hound::Error) -> Self {
+        Self::Decode(match err {
+            hound::Error::IoError(err) => creak::DecoderError::IOError(err),
+            err => creak::DecoderError::FormatError(format!("wav: {}", err)),
+        })
+    }
+}
+
+impl From<claxon::Error> for ImportError {
+    fn from(err: claxon::Error) -> Self {
+        Self::Decode(match err {
+            claxon::Error::IoError(err) => creak::DecoderError::IOError(err),
+            err => creak::DecoderError::FormatError(format!("flac: {}", err)),
+        })
+    }
+}
+
+fn downmix_frame<T: Copy + Into<f64>>(frame: &[T], downmix: Downmix) -> f64 {
+    match downmix {
+        Downmix::Average => frame.iter().map(|x| (*x).into()).sum::<f64>() / frame.len() as f64,
+        Downmix::Sum => frame.iter().map(|x| (*x).into()).sum(),
+        Downmix::Channel(which) => frame[which].into(),
+    }
+}
+
+/// An audio file opened for decoding, with its header read.
+///
+/// WAV and FLAC files are read with their own decoders, which hand out integer audio as it is
+/// stored rather than widened to floats like creak does, so that the sample can keep its bit
+/// depth. Anything else is left to creak.
+enum Reader {
+    Wav(hound::WavReader<std::io::BufReader<std::fs::File>>),
+    Flac(claxon::FlacReader<std::fs::File>),
+    Other(Box<creak::Decoder>),
+}
+
+/// Audio as decoded from a file.
+enum Decoded {
+    /// 16 or 24-bit integers, along with that bit depth.
+    Ints(Vec<i32>, u32),
+    Floats(Vec<f32>),
+}
+
+impl Reader {
+    /// Opens a file by its extension, like creak does.
+    fn open(path: &Path) -> Result<Self, ImportError> {
+        Ok(match path.extension().and_then(|ext| ext.to_str()) {
+            Some("wav") => Self::Wav(hound::WavReader::open(path)?),
+            Some("flac") => Self::Flac(claxon::FlacReader::open(path)?),
+            _ => Self::Other(Box::new(creak::Decoder::open(path)?)),
+        })
+    }
+
+    fn format(&self) -> AudioFormat {
+        match self {
+            Self::Wav(_) => AudioFormat::Wav,
+            Self::Flac(_) => AudioFormat::Flac,
+            Self::Other(decoder) => decoder.info().format(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self {
+            Self::Wav(reader) => reader.spec().sample_rate,
+            Self::Flac(reader) => reader.streaminfo().sample_rate,
+            Self::Other(decoder) => decoder.info().sample_rate(),
+        }
+    }
+
+    fn channels(&self) -> usize {
+        match self {
+            Self::Wav(reader) => reader.spec().channels as usize,
+            Self::Flac(reader) => reader.streaminfo().channels as usize,
+            Self::Other(decoder) => decoder.info().channels(),
+        }
+    }
+
+    /// The bit depth the file stores its audio at, for formats that have one.
+    fn bits_per_sample(&self) -> Option<u32> {
+        match self {
+            Self::Wav(reader) => Some(reader.spec().bits_per_sample as u32),
+            Self::Flac(reader) => Some(reader.streaminfo().bits_per_sample),
+            Self::Other(_) => None,
+        }
+    }
+
+    fn decode(self) -> Result<Decoded, ImportError> {
+        let (ints, bits) = match self {
+            Self::Wav(reader) => {
+                let spec = reader.spec();
+
+                if spec.sample_format == hound::SampleFormat::Float {
+                    return Ok(Decoded::Floats(
+                        reader.into_samples().collect::<Result<_, _>>()?,
+                    ));
+                }
+
+                let ints = reader.into_samples().collect::<Result<Vec<i32>, _>>()?;
+                (ints, spec.bits_per_sample as u32)
+            }
+            Self::Flac(mut reader) => {
+                let bits = reader.streaminfo().bits_per_sample;
+                (reader.samples().collect::<Result<Vec<i32>, _>>()?, bits)
+            }
+            Self::Other(decoder) => {
+                let floats = decoder.into_samples()?.collect::<Result<_, _>>()?;
+                return Ok(Decoded::Floats(floats));
+            }
+        };
+
+        Ok(match bits {
+            16 | 24 => Decoded::Ints(ints, bits),
+            // other bit depths are scaled to floats like creak does, by their largest value
+            _ => {
+                let max = ((1_i64 << (bits - 1)) - 1) as f32;
+                Decoded::Floats(ints.into_iter().map(|x| x as f32 / max).collect())
+            }
+        })
+    }
+}
+
+/// Integer audio at a bit depth of 16 or 24.
+fn int_data(values: Vec<i32>, bits: u32) -> SampleData {
+    if bits == 16 {
+        SampleData::I16(values.into_iter().map(|x| x as i16).collect())
+    } else {
+        SampleData::I24(values)
+    }
+}
+
+impl Sample {
+    /// Decodes any format supported by creak (WAV, FLAC, Ogg Vorbis and MP3).
+    ///
+    /// 16 and 24-bit integer WAV and FLAC audio is kept at its bit depth, and anything else as
+    /// 32-bit floats.
+    pub fn import<P: AsRef<Path>>(
+        path: P,
+        options: &ImportOptions,
+    ) -> Result<ImportedSample, ImportError> {
+        let reader = Reader::open(path.as_ref())?;
+        let format = reader.format();
+        let channels = reader.channels();
+        let bits_per_sample = reader.bits_per_sample();
+
+        if channels == 0 {
+            return Err(ImportError::NoChannels);
+        }
+
+        if let Some(Downmix::Channel(channel)) = options.downmix {
+            if channel >= channels {
+                return Err(ImportError::NoSuchChannel { channel, channels });
+            }
+        }
+
+        let baserate = reader.sample_rate() as f64;
+
+        // integer audio keeps its bit depth, while anything else is kept as 32-bit floats; a
+        // trailing partial frame is dropped, if any
+        let audio = match reader.decode()? {
+            Decoded::Ints(ints, bits) => {
+                let (min, max) = (-(1 << (bits - 1)) as f64, ((1 << (bits - 1)) - 1) as f64);
+                let values = match options.downmix {
+                    Some(downmix) => ints
+                        .chunks_exact(channels)
+                        .map(|frame| downmix_frame(frame, downmix).round().clamp(min, max) as i32)
+                        .collect(),
+                    None => ints.chunks_exact(channels).flatten().copied().collect(),
+                };
+
+                int_data(values, bits)
+            }
+            Decoded::Floats(floats) => SampleData::F32(match options.downmix {
+                Some(downmix) => floats
+                    .chunks_exact(channels)
+                    .map(|frame| downmix_frame(frame, downmix) as f32)
+                    .collect(),
+                None => floats.chunks_exact(channels).flatten().copied().collect(),
+            }),
+        };
+
+        let sample = match options.downmix {
+            Some(_) => Sample::mono(audio, baserate),
+            None => Sample {
+                audio,
+                baserate,
+                channels,
+                layout: ChannelLayout::Interleaved,
+            },
+        };
+
+        Ok(ImportedSample {
+            duration: sample.duration(),
+            sample,
+            format,
+            channels,
+            bits_per_sample,
+        })
+    }
+
+    /// Decodes an audio file, keeping all of its channels.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ImportError> {
+        Ok(Self::import(path, &ImportOptions::default())?.sample)
+    }
+}
@@ -1,27 +1,28 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use crate::common::*;
 
 impl LoopDef {
-    pub fn next_stop(&self, position: Position) -> Option<f64> {
+    pub fn section(&self) -> Option<LoopSection> {
         use LoopDef::*;
         match *self {
             None => Option::None,
+            Forward(section) | Backward(section) | PingPong(section) => Some(section),
+        }
+    }
 
-            Forward(section) => Some(if position.reversing && position.at < section.from {
-                0.0
-            } else if position.reversing {
-                section.from
-            } else {
-                section.to
-            }),
+    /// The point at which playback next has to turn or jump, if any is ahead of it.
+    pub fn next_stop(&self, position: Position) -> Option<f64> {
+        let section = self.section()?;
 
-            PingPong(section) => Some(if position.reversing && position.at < section.from {
-                0.0
-            } else if position.reversing {
-                section.from
-            } else {
-                section.to
-            }),
+        if position.reversing && position.at < section.from {
+            Some(0.0)
+        } else if position.reversing {
+            Some(section.from)
+        } else if position.at <= section.to {
+            Some(section.to)
+        } else {
+            // the loop is behind
+            Option::None
         }
     }
 
@@ -46,6 +47,19 @@ impl LoopDef {
                 }
             }),
 
+            // turns around at the end, then jumps back to it from the start
+            Backward(section) => Ok(if from.reversing && from.at < section.from {
+                Position {
+                    reversing: false,
+                    at: 0.0,
+                }
+            } else {
+                Position {
+                    reversing: true,
+                    at: section.to,
+                }
+            }),
+
             PingPong(section) => Ok(if from.reversing && from.at < section.from {
                 Position {
                     reversing: false,
@@ -80,18 +94,141 @@ pub enum SmoothingMode {
     Cosine(f64),
 }
 
+impl SmoothingMode {
+    /// The gain of the window at a position between 0 and 1 through it, which is zero outside of
+    /// that range.
+    pub fn window(&self, position: f64) -> f64 {
+        if !(0.0..=1.0).contains(&position) {
+            return 0.0;
+        }
+
+        let dist = f64::min(1.0 - position, position);
+
+        match self {
+            Self::None => 1.0,
+            Self::Triangle => dist * 2.0,
+            Self::Linear(width) => (dist / width).min(1.0),
+            Self::SquareRoot(width) => (dist / width).min(1.0).sqrt(),
+            Self::Cosine(width) => {
+                0.5 - 0.5 * ((dist / width).min(1.0) * std::f64::consts::PI).cos()
+            }
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct GranulatingMode {
     pub segment: LoopSection,
     pub interval: f64,
     pub gain: f64,
     pub smoothing: SmoothingMode,
+    /// Largest random offset of a granule's start in the sample, in seconds.
+    #[serde(default)]
+    pub position_jitter: f64,
+    /// Largest random pitch offset of a granule, in semitones.
+    #[serde(default)]
+    pub pitch_spread: f64,
+    /// Largest random panning of a granule, from 0 (centered) to 1.
+    #[serde(default)]
+    pub pan_spread: f64,
+    /// Largest random change of the interval before each granule, as a fraction of it.
+    #[serde(default)]
+    pub interval_jitter: f64,
+    /// How fast the segment moves through the sample, in seconds of sample per second. The
+    /// segment wraps around at the end of the sample.
+    #[serde(default)]
+    pub scan_speed: f64,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+impl GranulatingMode {
+    /// A granulating mode without any randomness or scanning.
+    pub fn new(segment: LoopSection, interval: f64, gain: f64, smoothing: SmoothingMode) -> Self {
+        Self {
+            segment,
+            interval,
+            gain,
+            smoothing,
+            position_jitter: 0.0,
+            pitch_spread: 0.0,
+            pan_spread: 0.0,
+            interval_jitter: 0.0,
+            scan_speed: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+/// The stages a granulating instrument goes through, one per NextLoop.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GranulatingStages {
+    pub stages: Vec<GranulatingMode>,
+    /// Length of the crossfade between the granules of two stages, in seconds. Without one,
+    /// the granules of the previous stage just play out.
+    #[serde(default)]
+    pub crossfade: f64,
+}
+
+impl From<GranulatingMode> for GranulatingStages {
+    fn from(mode: GranulatingMode) -> Self {
+        Self {
+            stages: vec![mode],
+            crossfade: 0.0,
+        }
+    }
+}
+
+/// Reads the stages of a granulating instrument, or the single mode it was saved with before
+/// it could have stages.
+fn deserialize_stages<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<GranulatingStages, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        Stages(GranulatingStages),
+        Single(GranulatingMode),
+    }
+
+    Ok(match Stored::deserialize(deserializer)? {
+        Stored::Stages(stages) => stages,
+        Stored::Single(mode) => mode.into(),
+    })
+}
+
+/// Plays a sample at the speed of the track's tempo, regardless of the note's pitch.
+///
+/// The sample is cut into overlapping grains, which play at the note's pitch and are laid out
+/// at the stretched speed. Grains start every half of their length, so the smoothing should be
+/// one that adds up to a flat gain at half overlap, like `Triangle` or `Cosine(0.5)`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TimeStretchMode {
+    pub start: f64,
+    /// The tempo the sample was recorded at, in beats per minute.
+    pub source_bpm: f64,
+    /// Length of a grain, in seconds.
+    pub grain: f64,
+    pub smoothing: SmoothingMode,
+}
+
+impl TimeStretchMode {
+    /// How many seconds of the sample play per second, at the given tempo.
+    pub fn stretch_ratio(&self, tempo: f64) -> f64 {
+        if self.source_bpm > 0.0 {
+            tempo / self.source_bpm
+        } else {
+            1.0
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub enum InstrumentMode {
     Basic(BasicMode),
-    Granulating(GranulatingMode),
+    #[serde(deserialize_with = "deserialize_stages")]
+    Granulating(GranulatingStages),
+    TimeStretch(TimeStretchMode),
 }
 
 use crate::renderer;
@@ -100,23 +237,64 @@ impl InstrumentMode {
         &self,
         data: std::sync::Arc<Project>,
         sample: usize,
+        start: NoteStart,
     ) -> Box<dyn renderer::SamplerState + 'a>
     where
         'static: 'a,
     {
         match self {
             Self::Basic(def) => {
-                Box::from(renderer::BasicSamplerState::new(data, sample, def.clone()))
+                Box::from(renderer::BasicSamplerState::new(
+                    data,
+                    sample,
+                    def.clone(),
+                    start,
+                ))
             }
             Self::Granulating(def) => Box::from(renderer::GranulatingSamplerState::new(
                 data,
                 sample,
                 def.clone(),
             )),
+            Self::TimeStretch(def) => Box::from(renderer::TimeStretchSamplerState::new(
+                data,
+                sample,
+                def.clone(),
+            )),
         }
     }
 }
 
+/// An inclusive range of pitches or volumes.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ValueRange {
+    pub from: f64,
+    pub to: f64,
+}
+
+impl ValueRange {
+    pub fn contains(&self, value: f64) -> bool {
+        self.from <= value && value <= self.to
+    }
+}
+
+/// A region of an instrument's pitch and volume space played by its own sample.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Zone {
+    pub pitch: ValueRange,
+    #[serde(default)]
+    pub volume: Option<ValueRange>,
+    pub sample: usize,
+    pub base_pitch: f64,
+    pub mode: InstrumentMode,
+}
+
+impl Zone {
+    pub fn matches(&self, pitch: f64, volume: f64) -> bool {
+        self.pitch.contains(pitch) && self.volume.is_none_or(|range| range.contains(volume))
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Instrument {
     pub sample: usize,
@@ -124,5 +302,24 @@ pub struct Instrument {
     pub pan: f64,
     pub base_pitch: f64,
     pub mode: InstrumentMode,
+    #[serde(default)]
+    pub zones: Vec<Zone>,
+    #[serde(default)]
+    pub volume_envelope: Option<Envelope>,
+    /// Pitch offset over time, in semitones.
+    #[serde(default)]
+    pub pitch_envelope: Option<Envelope>,
+    /// Panning offset over time, from -1 (left) to 1 (right).
+    #[serde(default)]
+    pub pan_envelope: Option<Envelope>,
+}
+
+impl Instrument {
+    /// The first zone that covers a note, if any.
+    ///
+    /// Notes outside of every zone play the instrument's own sample, base pitch and mode.
+    pub fn zone_for(&self, pitch: f64, volume: f64) -> Option<&Zone> {
+        self.zones.iter().find(|zone| zone.matches(pitch, volume))
+    }
 }
 
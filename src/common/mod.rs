@@ -1,11 +1,24 @@
+pub mod analysis;
+pub mod archive;
+pub mod buffer;
+pub mod envelope;
+pub mod import;
 pub mod instrument;
 pub mod pattern;
 pub mod position;
+pub mod processing;
 pub mod sample;
+pub mod stream;
 pub mod main;
 
+pub use analysis::*;
+pub use archive::*;
+pub use buffer::*;
+pub use envelope::*;
+pub use import::*;
 pub use instrument::*;
 pub use pattern::*;
 pub use position::*;
 pub use sample::*;
+pub use stream::*;
 pub use main::*;
@@ -1,5 +1,6 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
-use crate::common::*;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Slide {
@@ -7,18 +8,145 @@ pub struct Slide {
     pub amount: f64,
 }
 
+/// The shape of a vibration's cycle, which swings between -1 and 1.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum LfoWaveform {
+    #[default]
+    Sine,
+    Triangle,
+    Square,
+    SawUp,
+    SawDown,
+    /// A random value held for each cycle.
+    SampleAndHold {
+        seed: u64,
+    },
+}
+
+impl LfoWaveform {
+    /// The value of the waveform a given amount of cycles in. Every waveform but the random one
+    /// starts at 0 on its way up, like a sine.
+    pub fn value(&self, cycles: f64) -> f64 {
+        let phase = cycles.rem_euclid(1.0);
+
+        match *self {
+            Self::Sine => (phase * std::f64::consts::TAU).sin(),
+            Self::Triangle => 1.0 - 4.0 * ((phase + 0.25).rem_euclid(1.0) - 0.5).abs(),
+            Self::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Self::SawUp => 2.0 * (phase + 0.5).rem_euclid(1.0) - 1.0,
+            Self::SawDown => 1.0 - 2.0 * (phase + 0.5).rem_euclid(1.0),
+            // each cycle draws from its own seed, so that any cycle can be found
+            Self::SampleAndHold { seed } => {
+                StdRng::seed_from_u64(seed ^ cycles.floor() as i64 as u64).gen_range(-1.0..=1.0)
+            }
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Vibration {
+    /// Cycles per second.
     pub speed: f64,
     pub depth: f64,
+    #[serde(default)]
+    pub waveform: LfoWaveform,
+    /// Where in its cycle the vibration starts, as a fraction of it.
+    #[serde(default)]
+    pub phase: f64,
+    /// Seconds over which the depth rises from nothing to its full amount.
+    #[serde(default)]
+    pub fade_in: f64,
+}
+
+impl Vibration {
+    /// The offset of the vibration a given amount of seconds after it starts.
+    pub fn offset_at(&self, secs: f64) -> f64 {
+        let fade = if self.fade_in > 0.0 {
+            (secs / self.fade_in).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+
+        self.depth * fade * self.waveform.value(secs * self.speed + self.phase)
+    }
+}
+
+/// A span of time, either in seconds or in rows of the pattern it plays in.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Interval {
+    Seconds(f64),
+    Rows(f64),
+}
+
+impl Interval {
+    /// Length of the interval in seconds, given how many rows play per second if that is known.
+    pub fn secs(&self, row_speed: Option<f64>) -> Option<f64> {
+        match *self {
+            Self::Seconds(secs) => Some(secs),
+            Self::Rows(rows) => row_speed.map(|row_speed| rows / row_speed),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Retrigger {
+    pub interval: Interval,
+    /// Scales the volume of the note on each retrigger, if set.
+    #[serde(default)]
+    pub volume: Option<f64>,
+}
+
+/// The order an arpeggio steps through its offsets in.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum ArpeggioOrder {
+    /// As listed, starting over after the last one.
+    #[default]
+    Up,
+    /// From the last listed to the first, starting over after it.
+    Down,
+    /// Any of them on each step.
+    Random,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Arpeggio {
+    /// Offsets from the note's pitch, in semitones. The note itself only plays if 0 is among them.
+    pub offsets: Vec<f64>,
+    /// Steps per second.
+    pub rate: f64,
+    #[serde(default)]
+    pub order: ArpeggioOrder,
+    /// Seed of the random order, so that it plays the same every time.
+    #[serde(default)]
+    pub seed: u64,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub enum Effect {
+    /// Slides the pitch by `amount` semitones over `length` seconds.
     Portamento(Slide),
+    /// Glides a playing note to the pitch of this one over the given seconds, instead of
+    /// retriggering it.
+    TonePortamento(f64),
     Vibrato(Vibration),
     Tremolo(Vibration),
     Panbrello(Vibration),
+    /// Restarts the note's sample every interval, for as long as the effect lasts. Intervals in
+    /// rows only count in patterns.
+    Retrigger(Retrigger),
+    /// Holds back the start of the note by a fraction of its row. Whatever the channel played
+    /// keeps playing until then, and notes delayed past the end of their row never play.
+    NoteDelay(f64),
+    /// Silences the note after the given seconds, even if the effect is shorter.
+    NoteCut(f64),
+    /// Cycles the pitch through offsets from the note's pitch.
+    Arpeggio(Arpeggio),
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -27,6 +155,33 @@ pub struct EffectInstance {
     pub effect: Effect,
 }
 
+/// A point in a sample, either in seconds or as a fraction of its duration.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum SampleOffset {
+    Seconds(f64),
+    Fraction(f64),
+}
+
+impl SampleOffset {
+    pub fn secs(&self, duration: f64) -> f64 {
+        match *self {
+            Self::Seconds(secs) => secs,
+            Self::Fraction(fraction) => fraction * duration,
+        }
+    }
+}
+
+/// Where in its sample a note starts playing, for instruments that play through their sample.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct NoteStart {
+    /// Overrides the start of the instrument's mode.
+    #[serde(default)]
+    pub offset: Option<SampleOffset>,
+    /// Plays the note backwards. Without an offset, it starts from the end of the sample.
+    #[serde(default)]
+    pub reverse: bool,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct NoteInstruction {
     pub instrument: usize,
@@ -34,6 +189,29 @@ pub struct NoteInstruction {
     pub pan: f64,
     pub volume: f64,
     pub effects: Vec<EffectInstance>,
+    #[serde(default)]
+    pub start: NoteStart,
+}
+
+impl NoteInstruction {
+    /// The glide time of this note's tone portamento, if it has one.
+    pub fn glide_time(&self) -> Option<f64> {
+        self.effects.iter().find_map(|instance| match instance.effect {
+            Effect::TonePortamento(secs) => Some(secs),
+            _ => None,
+        })
+    }
+
+    /// The fraction of its row by which this note's start is held back.
+    pub fn delay(&self) -> f64 {
+        self.effects
+            .iter()
+            .find_map(|instance| match instance.effect {
+                Effect::NoteDelay(fraction) => Some(fraction.max(0.0)),
+                _ => None,
+            })
+            .unwrap_or(0.0)
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -1,13 +1,30 @@
 use serde::{Deserialize, Serialize};
-use crate::common::*;
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
 pub struct LoopSection {
-    from: f64,
-    to: f64,
+    pub from: f64,
+    pub to: f64,
+    /// Length of the crossfade of a loop that jumps, in seconds. The end the loop jumps from is
+    /// blended with the audio leading up to the end it jumps to, so that the jump is seamless.
+    #[serde(default)]
+    pub crossfade: f64,
+    /// How many times playback goes back around the loop before moving on to the next one, or
+    /// None to loop until told to. Going back around is a jump for forward and backward loops,
+    /// and a bounce for ping-pong loops.
+    #[serde(default)]
+    pub repeats: Option<u32>,
 }
 
 impl LoopSection {
+    pub fn new(from: f64, to: f64) -> Self {
+        Self {
+            from,
+            to,
+            crossfade: 0.0,
+            repeats: None,
+        }
+    }
+
     pub fn len(&self) -> f64 {
         self.to - self.from
     }
@@ -17,6 +34,11 @@ impl LoopSection {
 pub enum LoopDef {
     None,
     Forward(LoopSection),
+    /// Plays the section backwards, once playback reaches its end.
+    ///
+    /// Once the loop is left, playback goes on down to the section's start and then carries on
+    /// forward from its end.
+    Backward(LoopSection),
     PingPong(LoopSection),
 }
 
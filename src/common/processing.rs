@@ -0,0 +1,236 @@
+//! Destructive editing of samples.
+//!
+//! Every operation leaves the sample alone and returns an edited copy, in the same format and
+//! channel layout. Streamed samples are loaded into memory. Times are in seconds.
+
+use crate::common::*;
+
+impl Sample {
+    fn frame_at(&self, secs: f64) -> usize {
+        ((secs * self.baserate).round().max(0.0) as usize).min(self.frames())
+    }
+
+    /// The audio of each channel, as `f64`.
+    fn split_channels(&self) -> Vec<Vec<f64>> {
+        (0..self.channels)
+            .map(|c| {
+                let channel = self.channel(c);
+                (0..channel.len())
+                    .map(|i| channel.frame(i as isize))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// A sample like this one, with other audio for each channel.
+    fn with_channels(&self, channels: Vec<Vec<f64>>, baserate: f64) -> Self {
+        let audio: Vec<f64> = match self.layout {
+            ChannelLayout::Interleaved => {
+                let frames = channels.first().map_or(0, |channel| channel.len());
+                (0..frames)
+                    .flat_map(|i| channels.iter().map(move |channel| channel[i]))
+                    .collect()
+            }
+            ChannelLayout::Planar => channels.concat(),
+        };
+
+        Self {
+            audio: SampleData::from_f64(&audio, self.audio.format()),
+            baserate,
+            channels: self.channels,
+            layout: self.layout,
+        }
+    }
+
+    fn map_channels(&self, mut edit: impl FnMut(&mut Vec<f64>)) -> Self {
+        let mut channels = self.split_channels();
+        channels.iter_mut().for_each(&mut edit);
+        self.with_channels(channels, self.baserate)
+    }
+
+    /// The audio of another sample for each channel of this one, at this one's rate.
+    ///
+    /// A mono sample goes to every channel, and channels the other sample lacks are silent.
+    fn matching_channels(&self, other: &Sample, resampler: &dyn Resampler) -> Vec<Vec<f64>> {
+        let other = other.resample(self.baserate, resampler);
+        let frames = other.frames();
+
+        (0..self.channels)
+            .map(|c| {
+                let channel = other.channel(if other.channels == 1 { 0 } else { c });
+                (0..frames).map(|i| channel.frame(i as isize)).collect()
+            })
+            .collect()
+    }
+
+    /// Scales the sample so that its loudest value reaches `peak`. Silence stays silent.
+    pub fn normalize(&self, peak: f64) -> Self {
+        let loudest = self
+            .split_channels()
+            .iter()
+            .flatten()
+            .fold(0.0_f64, |loudest, x| loudest.max(x.abs()));
+
+        if loudest == 0.0 {
+            return self.map_channels(|_| {});
+        }
+
+        self.gain(peak / loudest)
+    }
+
+    pub fn gain(&self, gain: f64) -> Self {
+        self.map_channels(|channel| channel.iter_mut().for_each(|x| *x *= gain))
+    }
+
+    pub fn reverse(&self) -> Self {
+        self.map_channels(|channel| channel.reverse())
+    }
+
+    /// Keeps only the audio between two points in time.
+    pub fn trim(&self, from: f64, to: f64) -> Self {
+        let from = self.frame_at(from);
+        let to = self.frame_at(to).max(from);
+
+        self.map_channels(|channel| {
+            channel.truncate(to);
+            channel.drain(..from);
+        })
+    }
+
+    /// Fades the start of the sample in linearly.
+    pub fn fade_in(&self, length: f64) -> Self {
+        let frames = self.frame_at(length);
+
+        self.map_channels(|channel| {
+            for (i, x) in channel.iter_mut().take(frames).enumerate() {
+                *x *= i as f64 / frames as f64;
+            }
+        })
+    }
+
+    /// Fades the end of the sample out linearly, down to silence on its last frame.
+    pub fn fade_out(&self, length: f64) -> Self {
+        let frames = self.frame_at(length);
+
+        self.map_channels(|channel| {
+            for (i, x) in channel.iter_mut().rev().take(frames).enumerate() {
+                *x *= i as f64 / frames as f64;
+            }
+        })
+    }
+
+    /// Centers each channel around zero, by subtracting its average.
+    pub fn remove_dc_offset(&self) -> Self {
+        self.map_channels(|channel| {
+            if channel.is_empty() {
+                return;
+            }
+
+            let offset = channel.iter().sum::<f64>() / channel.len() as f64;
+            channel.iter_mut().for_each(|x| *x -= offset);
+        })
+    }
+
+    /// Inserts silence at a point in time, pushing what comes after it later.
+    pub fn insert_silence(&self, at: f64, length: f64) -> Self {
+        let at = self.frame_at(at);
+        let frames = (length * self.baserate).round().max(0.0) as usize;
+
+        self.map_channels(|channel| {
+            channel.splice(at..at, std::iter::repeat_n(0.0, frames));
+        })
+    }
+
+    /// Converts the sample to another base rate, keeping its duration and pitch.
+    ///
+    /// There is no filtering, so lowering the rate aliases whatever lies above the new Nyquist
+    /// frequency.
+    pub fn resample(&self, baserate: f64, resampler: &dyn Resampler) -> Self {
+        if baserate == self.baserate {
+            return self.map_channels(|_| {});
+        }
+
+        let frames = (self.duration() * baserate).round() as usize;
+        let step = self.baserate / baserate;
+
+        let channels = (0..self.channels)
+            .map(|c| {
+                let channel = self.channel(c);
+                (0..frames)
+                    .map(|i| resampler.interpolate(channel, i as f64 * step))
+                    .collect()
+            })
+            .collect();
+
+        self.with_channels(channels, baserate)
+    }
+
+    /// Mixes another sample into this one, starting at a point in time and scaled by `gain`.
+    ///
+    /// The sample grows if the other one goes past its end. The other sample is resampled to
+    /// this one's rate if needed.
+    pub fn mix_in(&self, other: &Sample, at: f64, gain: f64, resampler: &dyn Resampler) -> Self {
+        let at = self.frame_at(at);
+        let mut channels = self.split_channels();
+
+        for (channel, other) in channels
+            .iter_mut()
+            .zip(self.matching_channels(other, resampler))
+        {
+            if channel.len() < at + other.len() {
+                channel.resize(at + other.len(), 0.0);
+            }
+
+            for (x, y) in channel[at..].iter_mut().zip(other) {
+                *x += y * gain;
+            }
+        }
+
+        self.with_channels(channels, self.baserate)
+    }
+
+    /// Inserts another sample at a point in time, pushing what comes after it later.
+    ///
+    /// The other sample is resampled to this one's rate if needed.
+    pub fn paste(&self, other: &Sample, at: f64, resampler: &dyn Resampler) -> Self {
+        let at = self.frame_at(at);
+        let mut channels = self.split_channels();
+
+        for (channel, other) in channels
+            .iter_mut()
+            .zip(self.matching_channels(other, resampler))
+        {
+            channel.splice(at..at, other);
+        }
+
+        self.with_channels(channels, self.baserate)
+    }
+
+    /// Blends the end of a loop section with the audio leading up to its start, the way forward
+    /// playback crossfades it, and returns the section to loop over the result without fading.
+    pub fn bake_loop_crossfade(&self, section: LoopSection) -> (Self, LoopSection) {
+        let length = section.crossfade.min(section.from).min(section.len());
+        let baked = LoopSection {
+            crossfade: 0.0,
+            ..section
+        };
+
+        if length <= 0.0 {
+            return (self.map_channels(|_| {}), baked);
+        }
+
+        let tail = self.frame_at(section.to - length);
+        let head = self.frame_at(section.from - length);
+        let frames = self.frame_at(section.to) - tail;
+
+        let sample = self.map_channels(|channel| {
+            for i in 0..frames {
+                let progress = i as f64 / frames as f64;
+                channel[tail + i] =
+                    channel[tail + i] * (1.0 - progress) + channel[head + i] * progress;
+            }
+        });
+
+        (sample, baked)
+    }
+}
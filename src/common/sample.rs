@@ -1,9 +1,407 @@
-use serde::{Deserialize, Serialize};
-use crate::common::*;
+use crate::common::StreamedAudio;
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// The type each value of a sample's audio is stored as.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SampleFormat {
+    #[default]
+    F64,
+    F32,
+    I16,
+    I24,
+}
+
+impl SampleFormat {
+    /// Size in bytes of a value, as stored in files. 24-bit values take three bytes.
+    pub fn size(self) -> usize {
+        match self {
+            Self::F64 => 8,
+            Self::F32 => 4,
+            Self::I16 => 2,
+            Self::I24 => 3,
+        }
+    }
+}
+
+const I16_SCALE: f64 = 32768.0;
+const I24_SCALE: f64 = 8388608.0;
+
+/// Audio values, kept at the bit depth they came in.
+///
+/// Integer values are scaled so that their full range reads between -1 and 1. 24-bit values are
+/// stored in the low bits of an `i32`. Streamed values stay on disk, and are read as they play.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SampleData {
+    F64(Vec<f64>),
+    F32(Vec<f32>),
+    I16(Vec<i16>),
+    I24(Vec<i32>),
+    Streamed(StreamedAudio),
+}
+
+impl SampleData {
+    /// Converts audio to the given format, rounding and clipping it to the range of integer
+    /// formats.
+    pub fn from_f64(audio: &[f64], format: SampleFormat) -> Self {
+        let quantize = |x: f64, scale: f64| (x * scale).round().clamp(-scale, scale - 1.0);
+
+        match format {
+            SampleFormat::F64 => Self::F64(audio.to_vec()),
+            SampleFormat::F32 => Self::F32(audio.iter().map(|x| *x as f32).collect()),
+            SampleFormat::I16 => Self::I16(
+                audio
+                    .iter()
+                    .map(|x| quantize(*x, I16_SCALE) as i16)
+                    .collect(),
+            ),
+            SampleFormat::I24 => Self::I24(
+                audio
+                    .iter()
+                    .map(|x| quantize(*x, I24_SCALE) as i32)
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        match self {
+            Self::F64(_) => SampleFormat::F64,
+            Self::F32(_) => SampleFormat::F32,
+            Self::I16(_) => SampleFormat::I16,
+            Self::I24(_) => SampleFormat::I24,
+            Self::Streamed(audio) => audio.format(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::F64(audio) => audio.len(),
+            Self::F32(audio) => audio.len(),
+            Self::I16(audio) => audio.len(),
+            Self::I24(audio) => audio.len(),
+            Self::Streamed(audio) => audio.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The value at an index, converted to `f64`.
+    pub fn get(&self, index: usize) -> f64 {
+        match self {
+            Self::F64(audio) => audio[index],
+            Self::F32(audio) => audio[index] as f64,
+            Self::I16(audio) => audio[index] as f64 / I16_SCALE,
+            Self::I24(audio) => audio[index] as f64 / I24_SCALE,
+            Self::Streamed(audio) => audio.get(index),
+        }
+    }
+
+    /// Decodes raw little-endian values, or returns None if the bytes do not add up to a whole
+    /// number of values.
+    pub fn from_le_bytes(bytes: &[u8], format: SampleFormat) -> Option<Self> {
+        let size = format.size();
+
+        if !bytes.len().is_multiple_of(size) {
+            return None;
+        }
+
+        let values = bytes.chunks_exact(size);
+
+        Some(match format {
+            SampleFormat::F64 => Self::F64(
+                values
+                    .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()))
+                    .collect(),
+            ),
+            SampleFormat::F32 => Self::F32(
+                values
+                    .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+                    .collect(),
+            ),
+            SampleFormat::I16 => Self::I16(
+                values
+                    .map(|chunk| i16::from_le_bytes(chunk.try_into().unwrap()))
+                    .collect(),
+            ),
+            // sign-extend from the top byte
+            SampleFormat::I24 => Self::I24(
+                values
+                    .map(|chunk| i32::from_le_bytes([0, chunk[0], chunk[1], chunk[2]]) >> 8)
+                    .collect(),
+            ),
+        })
+    }
+
+    /// Encodes the values as raw little-endian bytes of their format.
+    pub fn to_le_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len() * self.format().size());
+
+        match self {
+            Self::F64(audio) => audio.iter().for_each(|x| bytes.extend(x.to_le_bytes())),
+            Self::F32(audio) => audio.iter().for_each(|x| bytes.extend(x.to_le_bytes())),
+            Self::I16(audio) => audio.iter().for_each(|x| bytes.extend(x.to_le_bytes())),
+            Self::I24(audio) => audio
+                .iter()
+                .for_each(|x| bytes.extend(&x.to_le_bytes()[..3])),
+            Self::Streamed(audio) => {
+                bytes = Self::from_f64(&self.to_f64(), audio.format()).to_le_bytes();
+            }
+        }
+
+        bytes
+    }
+
+    /// Every value, converted to `f64`.
+    pub fn to_f64(&self) -> Vec<f64> {
+        (0..self.len()).map(|i| self.get(i)).collect()
+    }
+}
+
+impl From<Vec<f64>> for SampleData {
+    fn from(audio: Vec<f64>) -> Self {
+        Self::F64(audio)
+    }
+}
+
+/// Reads sample audio either in its typed form or as a plain list of `f64` values, which is how
+/// projects used to store it.
+fn deserialize_audio<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SampleData, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        Plain(Vec<f64>),
+        Typed(SampleData),
+    }
+
+    Ok(match Stored::deserialize(deserializer)? {
+        Stored::Plain(audio) => SampleData::F64(audio),
+        Stored::Typed(audio) => audio,
+    })
+}
+
+/// How the channels of a multi-channel sample are laid out in its audio.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChannelLayout {
+    /// One frame after the other, with the channels of each frame next to each other.
+    #[default]
+    Interleaved,
+
+    /// One channel after the other, each holding every frame.
+    Planar,
+}
+
+pub(super) fn one_channel() -> usize {
+    1
+}
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Sample {
-    pub audio: Vec<f64>,
+    #[serde(deserialize_with = "deserialize_audio")]
+    pub audio: SampleData,
     pub baserate: f64,
+    #[serde(default = "one_channel")]
+    pub channels: usize,
+    #[serde(default)]
+    pub layout: ChannelLayout,
+}
+
+/// A view of a single channel of a sample's audio.
+///
+/// Frames outside of the channel read as silence.
+#[derive(Clone, Copy)]
+pub struct AudioChannel<'a> {
+    audio: &'a SampleData,
+    offset: usize,
+    stride: usize,
+    len: usize,
+}
+
+impl<'a> AudioChannel<'a> {
+    /// Length in frames.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn frame(&self, at: isize) -> f64 {
+        if at < 0 || at as usize >= self.len {
+            0.0
+        } else {
+            self.audio.get(self.offset + at as usize * self.stride)
+        }
+    }
+}
+
+impl AudioChannel<'_> {
+    /// Whether the channel is read from disk as it plays.
+    pub fn is_streamed(&self) -> bool {
+        matches!(self.audio, SampleData::Streamed(_))
+    }
+
+    /// Copies the frames in `from..to` out of the channel at once, so that a streamed sample's
+    /// cache is only locked once for all of them.
+    pub fn window(&self, from: isize, to: isize) -> ChannelWindow {
+        let mut frames = vec![0.0; (to - from).max(0) as usize];
+
+        // only the frames within the channel are read, and the rest stay silent
+        let first = from.max(0);
+        let last = to.min(self.len as isize);
+
+        if first < last {
+            let out = &mut frames[(first - from) as usize..(last - from) as usize];
+            let at = self.offset + first as usize * self.stride;
+
+            match self.audio {
+                SampleData::Streamed(audio) => audio.read(at, self.stride, out),
+                audio => {
+                    for (i, x) in out.iter_mut().enumerate() {
+                        *x = audio.get(at + i * self.stride);
+                    }
+                }
+            }
+        }
+
+        ChannelWindow {
+            audio: SampleData::F64(frames),
+            start: from,
+        }
+    }
+}
+
+/// Frames copied out of a channel, by [`AudioChannel::window`].
+pub struct ChannelWindow {
+    audio: SampleData,
+    start: isize,
+}
+
+impl ChannelWindow {
+    /// The copied frames, the first of which is frame 0.
+    pub fn channel(&self) -> AudioChannel<'_> {
+        AudioChannel {
+            audio: &self.audio,
+            offset: 0,
+            stride: 1,
+            len: self.audio.len(),
+        }
+    }
+
+    /// The frame of the original channel that the window starts at.
+    pub fn start(&self) -> isize {
+        self.start
+    }
 }
 
+static NO_AUDIO: SampleData = SampleData::F64(Vec::new());
+
+impl AudioChannel<'_> {
+    /// A channel without any frames, which only reads as silence.
+    pub fn silent() -> Self {
+        Self {
+            audio: &NO_AUDIO,
+            offset: 0,
+            stride: 1,
+            len: 0,
+        }
+    }
+}
+
+impl Sample {
+    pub fn mono(audio: impl Into<SampleData>, baserate: f64) -> Self {
+        Self {
+            audio: audio.into(),
+            baserate,
+            channels: 1,
+            layout: ChannelLayout::Interleaved,
+        }
+    }
+
+    /// Length of the sample, in frames of all of its channels.
+    pub fn frames(&self) -> usize {
+        self.audio.len() / self.channels.max(1)
+    }
+
+    /// Length of the sample, in seconds.
+    pub fn duration(&self) -> f64 {
+        self.frames() as f64 / self.baserate
+    }
+
+    /// One of the channels of the sample, which is silent if it has no such channel.
+    pub fn channel(&self, which: usize) -> AudioChannel<'_> {
+        if which >= self.channels {
+            return AudioChannel::silent();
+        }
+
+        let len = self.frames();
+
+        match self.layout {
+            ChannelLayout::Interleaved => AudioChannel {
+                audio: &self.audio,
+                offset: which,
+                stride: self.channels,
+                len,
+            },
+            ChannelLayout::Planar => AudioChannel {
+                audio: &self.audio,
+                offset: which * len,
+                stride: 1,
+                len,
+            },
+        }
+    }
+
+    /// The channels that play on the left and right side.
+    ///
+    /// Mono samples play on both, and channels past the second one are not played.
+    pub fn stereo(&self) -> [AudioChannel<'_>; 2] {
+        if self.channels == 1 {
+            [self.channel(0), self.channel(0)]
+        } else {
+            [self.channel(0), self.channel(1)]
+        }
+    }
+
+    /// Whether the sample has a stereo image of its own, rather than one channel for both sides.
+    pub fn is_stereo(&self) -> bool {
+        self.channels > 1
+    }
+
+    /// The same audio, laid out differently.
+    pub fn with_layout(&self, layout: ChannelLayout) -> Self {
+        if layout == self.layout {
+            return self.clone();
+        }
+
+        let channels: Vec<_> = (0..self.channels).map(|c| self.channel(c)).collect();
+        let frames = self.frames();
+
+        let audio: Vec<f64> = match layout {
+            ChannelLayout::Interleaved => (0..frames)
+                .flat_map(|i| channels.iter().map(move |c| c.frame(i as isize)))
+                .collect(),
+            ChannelLayout::Planar => channels
+                .iter()
+                .flat_map(|c| (0..frames).map(move |i| c.frame(i as isize)))
+                .collect(),
+        };
+
+        Self {
+            audio: SampleData::from_f64(&audio, self.audio.format()),
+            baserate: self.baserate,
+            channels: self.channels,
+            layout,
+        }
+    }
+
+    /// The same audio, stored in a different format.
+    pub fn with_format(&self, format: SampleFormat) -> Self {
+        Self {
+            audio: SampleData::from_f64(&self.audio.to_f64(), format),
+            ..self.clone()
+        }
+    }
+}
@@ -0,0 +1,438 @@
+//! Sample audio that is read from disk as it plays, instead of being kept in memory.
+
+use crate::common::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Amount of values read from disk at once.
+const BLOCK_VALUES: usize = 1 << 16;
+
+/// Amount of blocks each streamed sample keeps in memory.
+const CACHED_BLOCKS: usize = 8;
+
+/// Where the raw little-endian values of a streamed sample are, in a file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StreamSource {
+    pub path: PathBuf,
+    /// Position of the first value in the file, in bytes.
+    pub offset: u64,
+    /// Amount of values, across all channels.
+    pub len: usize,
+    pub format: SampleFormat,
+}
+
+struct CachedBlock {
+    index: usize,
+    values: SampleData,
+    last_used: u64,
+}
+
+/// A block being read ahead on another thread.
+struct Prefetch {
+    index: usize,
+    thread: JoinHandle<io::Result<SampleData>>,
+}
+
+#[derive(Default)]
+struct StreamCache {
+    file: Option<Arc<File>>,
+    blocks: Vec<CachedBlock>,
+    clock: u64,
+    last_block: Option<usize>,
+    prefetch: Option<Prefetch>,
+    misses: u64,
+}
+
+/// Reads raw bytes from a position in a file, without moving a cursor that other threads share.
+#[cfg(unix)]
+fn read_at(file: &File, offset: u64, bytes: &mut [u8]) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, bytes, offset)
+}
+
+/// Reads raw bytes from a position in a file, without moving a cursor that other threads share.
+#[cfg(windows)]
+fn read_at(file: &File, mut offset: u64, mut bytes: &mut [u8]) -> io::Result<()> {
+    while !bytes.is_empty() {
+        match std::os::windows::fs::FileExt::seek_read(file, bytes, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => {
+                bytes = &mut bytes[read..];
+                offset += read as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads raw bytes from a position in a file. Other threads reading the same file may move its
+/// cursor in between, so only the render thread reads on these platforms.
+#[cfg(not(any(unix, windows)))]
+fn read_at(mut file: &File, offset: u64, bytes: &mut [u8]) -> io::Result<()> {
+    use std::io::{Read, SeekFrom};
+
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(bytes)
+}
+
+fn read_block(file: &File, source: &StreamSource, index: usize) -> io::Result<SampleData> {
+    let from = index * BLOCK_VALUES;
+    let len = BLOCK_VALUES.min(source.len - from);
+    let mut bytes = vec![0; len * source.format.size()];
+
+    read_at(
+        file,
+        source.offset + (from * source.format.size()) as u64,
+        &mut bytes,
+    )?;
+
+    Ok(SampleData::from_le_bytes(&bytes, source.format).unwrap())
+}
+
+impl StreamCache {
+    /// The file the values are read from.
+    ///
+    /// The file stays open once it is, so the values keep being read from it even if another
+    /// file takes its place.
+    fn file(&mut self, source: &StreamSource) -> io::Result<Arc<File>> {
+        match &self.file {
+            Some(file) => Ok(file.clone()),
+            None => Ok(self
+                .file
+                .insert(Arc::new(File::open(&source.path)?))
+                .clone()),
+        }
+    }
+
+    /// Reads the raw bytes of the values from an index on, filling `bytes`.
+    fn read_raw(&mut self, source: &StreamSource, from: usize, bytes: &mut [u8]) -> io::Result<()> {
+        let file = self.file(source)?;
+        read_at(
+            &file,
+            source.offset + (from * source.format.size()) as u64,
+            bytes,
+        )
+    }
+
+    /// Adds a block to the cache, evicting the least recently used one if it is full.
+    fn insert(&mut self, index: usize, values: SampleData) {
+        if self.blocks.len() >= CACHED_BLOCKS {
+            let oldest = (0..self.blocks.len())
+                .min_by_key(|i| self.blocks[*i].last_used)
+                .unwrap();
+            self.blocks.swap_remove(oldest);
+        }
+
+        self.blocks.push(CachedBlock {
+            index,
+            values,
+            last_used: self.clock,
+        });
+    }
+
+    /// Takes the block read ahead into the cache once it is read, or waits for it if `wait` is
+    /// set. A block that could not be read is left out, to be read again when it is needed.
+    fn collect_prefetch(&mut self, wait: bool) {
+        if !self
+            .prefetch
+            .as_ref()
+            .is_some_and(|prefetch| wait || prefetch.thread.is_finished())
+        {
+            return;
+        }
+
+        let prefetch = self.prefetch.take().unwrap();
+
+        match prefetch.thread.join() {
+            Ok(Ok(values)) => self.insert(prefetch.index, values),
+            Ok(Err(err)) => log::warn!("could not read ahead: {}", err),
+            Err(_) => log::warn!("reading ahead panicked"),
+        }
+    }
+
+    /// Makes sure a block is in the cache, reading it if it is neither cached nor being read
+    /// ahead.
+    fn load(&mut self, source: &StreamSource, index: usize) {
+        self.clock += 1;
+
+        // a block being read ahead is waited for, rather than read again
+        if self
+            .prefetch
+            .as_ref()
+            .is_some_and(|prefetch| prefetch.index == index)
+        {
+            self.collect_prefetch(true);
+        }
+
+        if let Some(block) = self.blocks.iter_mut().find(|block| block.index == index) {
+            block.last_used = self.clock;
+            return;
+        }
+
+        self.misses += 1;
+
+        let values = self
+            .file(source)
+            .and_then(|file| read_block(&file, source, index))
+            .unwrap_or_else(|err| {
+                // an unreadable block plays as silence, and is tried again once it is evicted
+                log::warn!("could not read {}: {}", source.path.display(), err);
+                SampleData::F64(vec![])
+            });
+
+        self.insert(index, values);
+    }
+
+    /// Starts reading the block after `index`, in whichever direction playback moves through
+    /// the blocks, on another thread. Only one block is read ahead at a time.
+    fn read_ahead(&mut self, source: &StreamSource, index: usize) {
+        let ahead = match self.last_block {
+            Some(last) if last > index => index.checked_sub(1),
+            _ => Some(index + 1),
+        };
+
+        let Some(ahead) = ahead.filter(|ahead| ahead * BLOCK_VALUES < source.len) else {
+            return;
+        };
+
+        if self.prefetch.is_some() || self.blocks.iter().any(|block| block.index == ahead) {
+            return;
+        }
+
+        let Ok(file) = self.file(source) else {
+            return;
+        };
+        let source = source.clone();
+
+        self.prefetch = Some(Prefetch {
+            index: ahead,
+            thread: thread::spawn(move || read_block(&file, &source, ahead)),
+        });
+    }
+
+    fn get(&mut self, source: &StreamSource, at: usize) -> f64 {
+        let index = at / BLOCK_VALUES;
+
+        self.collect_prefetch(false);
+
+        if !self.blocks.iter().any(|block| block.index == index) {
+            self.load(source, index);
+        }
+
+        if self.last_block != Some(index) {
+            self.read_ahead(source, index);
+            self.last_block = Some(index);
+        }
+
+        let block = self
+            .blocks
+            .iter()
+            .find(|block| block.index == index)
+            .unwrap();
+        let offset = at - index * BLOCK_VALUES;
+
+        if offset < block.values.len() {
+            block.values.get(offset)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Audio values streamed from a file through a cache of recently read blocks.
+///
+/// Once playback moves into a block, the next one in the direction it moves is read ahead on
+/// another thread. A read that misses both waits for its block to be read from disk. Clones
+/// share their cache. Reads that fail play as silence, and are logged.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "StreamSource", into = "StreamSource")]
+pub struct StreamedAudio {
+    source: StreamSource,
+    cache: Arc<Mutex<StreamCache>>,
+}
+
+impl StreamedAudio {
+    pub fn new(source: StreamSource) -> Self {
+        Self {
+            source,
+            cache: Arc::default(),
+        }
+    }
+
+    pub fn source(&self) -> &StreamSource {
+        &self.source
+    }
+
+    pub fn len(&self) -> usize {
+        self.source.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.source.len == 0
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        self.source.format
+    }
+
+    /// How many blocks had to be read from disk when they were needed, rather than being found
+    /// in the cache or read ahead.
+    pub fn misses(&self) -> u64 {
+        self.cache.lock().unwrap().misses
+    }
+
+    /// The value at an index, converted to `f64`.
+    pub fn get(&self, index: usize) -> f64 {
+        if index >= self.source.len {
+            return 0.0;
+        }
+
+        self.cache.lock().unwrap().get(&self.source, index)
+    }
+
+    /// Reads values `stride` apart from an index on, converted to `f64`, locking the cache once
+    /// for all of them. Values past the end read as silence.
+    pub fn read(&self, from: usize, stride: usize, out: &mut [f64]) {
+        let mut cache = self.cache.lock().unwrap();
+
+        for (i, x) in out.iter_mut().enumerate() {
+            let index = from + i * stride;
+
+            *x = if index < self.source.len {
+                cache.get(&self.source, index)
+            } else {
+                0.0
+            };
+        }
+    }
+
+    /// Copies the raw values into a writer, a block at a time, without caching them.
+    ///
+    /// They are read from the same open file as playback reads them from, so they can be copied
+    /// into a file that replaces it.
+    pub fn copy_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let size = self.source.format.size();
+        let mut buffer = vec![0; BLOCK_VALUES * size];
+        let mut from = 0;
+
+        while from < self.source.len {
+            let len = BLOCK_VALUES.min(self.source.len - from);
+            let bytes = &mut buffer[..len * size];
+
+            // the cache is only locked for one block, so playback is not held up for long
+            self.cache
+                .lock()
+                .unwrap()
+                .read_raw(&self.source, from, bytes)?;
+            writer.write_all(bytes)?;
+            from += len;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<StreamSource> for StreamedAudio {
+    fn from(source: StreamSource) -> Self {
+        Self::new(source)
+    }
+}
+
+impl From<StreamedAudio> for StreamSource {
+    fn from(audio: StreamedAudio) -> Self {
+        audio.source
+    }
+}
+
+impl fmt::Debug for StreamedAudio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("StreamedAudio").field(&self.source).finish()
+    }
+}
+
+impl PartialEq for StreamedAudio {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+#[derive(Debug)]
+pub enum StreamError {
+    Io(io::Error),
+    Wav(hound::Error),
+    UnsupportedFormat { bits: u16, float: bool },
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+            Self::Wav(err) => write!(f, "invalid WAV file: {}", err),
+            Self::UnsupportedFormat { bits, float } => write!(
+                f,
+                "cannot stream {}-bit {} audio",
+                bits,
+                if *float { "float" } else { "integer" }
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+impl From<io::Error> for StreamError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<hound::Error> for StreamError {
+    fn from(err: hound::Error) -> Self {
+        Self::Wav(err)
+    }
+}
+
+impl Sample {
+    /// Opens a WAV file to be streamed as it plays, rather than decoded up front.
+    ///
+    /// Supports 16 and 24-bit integer and 32-bit float audio, with any number of channels.
+    pub fn stream_wav<P: AsRef<Path>>(path: P) -> Result<Self, StreamError> {
+        // the reader is left right at the start of the audio data once the header is read
+        let reader = hound::WavReader::new(File::open(&path)?)?;
+        let spec = reader.spec();
+        let len = reader.len() as usize;
+        let offset = reader.into_inner().stream_position()?;
+
+        use hound::SampleFormat::*;
+        let format = match (spec.sample_format, spec.bits_per_sample) {
+            (Int, 16) => SampleFormat::I16,
+            (Int, 24) => SampleFormat::I24,
+            (Float, 32) => SampleFormat::F32,
+            (sample_format, bits) => {
+                return Err(StreamError::UnsupportedFormat {
+                    bits,
+                    float: sample_format == Float,
+                })
+            }
+        };
+
+        Ok(Self {
+            audio: SampleData::Streamed(StreamedAudio::new(StreamSource {
+                path: path.as_ref().to_owned(),
+                offset,
+                len,
+                format,
+            })),
+            baserate: spec.sample_rate as f64,
+            channels: spec.channels as usize,
+            layout: ChannelLayout::Interleaved,
+        })
+    }
+}
@@ -3,12 +3,23 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
 mod app;
+mod cli;
 
 use crate::app::top::MyApp;
 use eframe::egui;
 
 fn main() -> Result<(), eframe::Error> {
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
+
+    let mut args = std::env::args().skip(1);
+    if let Some("render") = args.next().as_deref() {
+        if let Err(err) = cli::render(args) {
+            eprintln!("condemus render: {}", err);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let options = eframe::NativeOptions {
         initial_window_size: Some(egui::vec2(320.0, 240.0)),
         ..Default::default()
@@ -0,0 +1,625 @@
+use crate::common;
+use crate::common::*;
+use crate::renderer::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct EffectState {
+    def: EffectInstance,
+    frames: usize, // frames rendered since the effect started
+    pos: f64,
+}
+
+impl EffectState {
+    pub fn new(def: EffectInstance) -> Self {
+        Self {
+            def,
+            frames: 0,
+            pos: 0.0,
+        }
+    }
+
+    fn expired(&self) -> bool {
+        // a note cut lasts until it cuts, however short the effect is
+        let length = match self.def.effect {
+            Effect::NoteCut(secs) => self.def.length.max(secs),
+            _ => self.def.length,
+        };
+
+        self.pos >= length
+    }
+
+    /// Advances the effect by an amount of frames. Its position is counted in whole frames, so
+    /// that it does not depend on how the output is split in blocks.
+    pub fn advance(&mut self, frames: usize, rate: f64) -> bool {
+        self.frames += frames;
+        self.pos = self.frames as f64 / rate;
+        self.expired()
+    }
+
+    /// The frame, counted from the start of the effect, of its next retrigger or cut, if it has
+    /// one coming.
+    fn next_event(&self, rate: f64, row_speed: Option<f64>) -> Option<usize> {
+        match &self.def.effect {
+            Effect::Retrigger(retrigger) => {
+                let interval = retrigger.interval.secs(row_speed)?;
+
+                if interval <= 0.0 {
+                    return None;
+                }
+
+                let frame_of = |k: f64| (k * interval * rate).round() as usize;
+                let mut k = (self.frames as f64 / (interval * rate)).floor() + 1.0;
+
+                while frame_of(k) <= self.frames {
+                    k += 1.0;
+                }
+
+                (k * interval <= self.def.length).then(|| frame_of(k))
+            }
+            Effect::NoteCut(secs) => {
+                Some(((secs * rate).round() as usize).max(1)).filter(|at| *at > self.frames)
+            }
+            _ => None,
+        }
+    }
+
+    /// What the effect adds to the pitch, volume and panning of its channel at this point.
+    fn modulation(&self) -> (f64, f64, f64) {
+        use Effect::*;
+        match &self.def.effect {
+            Vibrato(vibrato) => (vibrato.offset_at(self.pos), 0.0, 0.0),
+            Tremolo(tremolo) => (0.0, tremolo.offset_at(self.pos), 0.0),
+            Panbrello(panbrello) => (0.0, 0.0, panbrello.offset_at(self.pos)),
+            Portamento(portamento) => (portamento.amount * slid(portamento, self.pos), 0.0, 0.0),
+            Arpeggio(arpeggio) => (arpeggio_offset(arpeggio, self.pos), 0.0, 0.0),
+            TonePortamento(_) | Retrigger(_) | NoteDelay(_) | NoteCut(_) => (0.0, 0.0, 0.0),
+        }
+    }
+}
+
+/// How much of a slide is done at a point in it, from 0 to 1.
+fn slid(slide: &Slide, pos: f64) -> f64 {
+    if slide.length <= 0.0 {
+        1.0
+    } else {
+        (pos / slide.length).clamp(0.0, 1.0)
+    }
+}
+
+/// The offset an arpeggio adds to the pitch at a point in it, in semitones.
+fn arpeggio_offset(arpeggio: &Arpeggio, pos: f64) -> f64 {
+    if arpeggio.offsets.is_empty() {
+        return 0.0;
+    }
+
+    let len = arpeggio.offsets.len();
+    let step = (pos * arpeggio.rate).max(0.0).floor() as u64;
+
+    let index = match arpeggio.order {
+        ArpeggioOrder::Up => step as usize % len,
+        ArpeggioOrder::Down => len - 1 - step as usize % len,
+        // each step draws from its own seed, so that any step can be found
+        ArpeggioOrder::Random => StdRng::seed_from_u64(arpeggio.seed ^ step).gen_range(0..len),
+    };
+
+    arpeggio.offsets[index]
+}
+
+/// A linear fade out of a channel.
+#[derive(Clone, Copy)]
+struct FadeState {
+    from: f64,
+    elapsed: f64,
+    length: f64,
+}
+
+impl FadeState {
+    fn gain_at(&self, secs: f64) -> f64 {
+        if self.length <= 0.0 {
+            0.0
+        } else {
+            self.from * (1.0 - (self.elapsed + secs) / self.length).max(0.0)
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.elapsed >= self.length
+    }
+}
+
+/// How many frames pass between updates of control rate parameters, like pitch.
+pub const CONTROL_FRAMES: usize = 64;
+
+/// Gains of the left and right side for a panning between -1 and 1.
+///
+/// A mono sample is split between both sides, while a stereo one keeps its image and is
+/// balanced, turning down only the side it is panned away from. Either plays at half volume on
+/// both sides when centered.
+fn pan_gains(panning: f64, stereo: bool) -> (f64, f64) {
+    let panning = panning.clamp(-1.0, 1.0);
+
+    if stereo {
+        ((1.0 - panning).min(1.0) / 2.0, (1.0 + panning).min(1.0) / 2.0)
+    } else {
+        ((1.0 - panning) / 2.0, (1.0 + panning) / 2.0)
+    }
+}
+
+/// A tone portamento in progress, which bends the pitch from where it was towards the new one.
+#[derive(Clone, Copy)]
+struct GlideState {
+    offset: f64, // semitones from the new pitch, at the start of the glide
+    length: f64,
+    frames: usize,
+    pos: f64,
+}
+
+impl GlideState {
+    fn offset(&self) -> f64 {
+        self.offset * (1.0 - (self.pos / self.length).min(1.0))
+    }
+
+    /// Advances the glide by an amount of frames, counted like those of effects.
+    fn advance(&mut self, frames: usize, rate: f64) -> bool {
+        self.frames += frames;
+        self.pos = self.frames as f64 / rate;
+        self.pos >= self.length
+    }
+}
+
+/// Values of a channel's parameters, which are held for a control period.
+#[derive(Clone, Copy, Default)]
+struct Controls {
+    pitch_rate: f64,
+    volume: f64,
+    panning: f64,
+}
+
+/// A playing note.
+///
+/// Its volume, panning and pitch are base values, which effects and glides never change as they
+/// go. They add offsets on top instead, which are evaluated anew for each control period, so that
+/// nothing drifts and the output does not depend on how it is split in blocks.
+pub struct ChannelState {
+    volume: f64,
+    panning: f64,
+    pitch: f64,
+    effects: Vec<EffectState>,
+    sampler: Box<dyn SamplerState>,
+    data: Arc<Project>,
+    instrument: usize,
+    sample: usize,
+    mode: InstrumentMode,
+    start: NoteStart,
+    base_pitch: f64,
+    tempo: Option<f64>,
+    row_speed: Option<f64>, // rows per second, if playing in a pattern
+    cut: bool,
+    released: bool,
+    paused: bool,
+    fade: Option<FadeState>,
+    glide: Option<GlideState>,
+    volume_envelope: EnvelopeState,
+    pitch_envelope: EnvelopeState,
+    pan_envelope: EnvelopeState,
+    frame: usize,
+    controls: Controls,
+    scratch: (Vec<f64>, Vec<f64>),
+}
+
+impl ChannelState {
+    pub fn get_sample(&self) -> &Sample {
+        &self.data.samples[self.sample]
+    }
+
+    fn get_instrument(&self) -> &Instrument {
+        &self.data.instruments[self.instrument]
+    }
+
+    pub fn new(data: Arc<Project>, sample: usize, instrument: usize, pitch: f64) -> Self {
+        let mode = data.instruments[instrument].mode.clone();
+        let sampler = mode.new_sampler(data.clone(), sample, NoteStart::default());
+        let base_pitch = data.instruments[instrument].base_pitch;
+
+        Self {
+            data,
+            instrument,
+            sample,
+            mode,
+            start: NoteStart::default(),
+            base_pitch,
+            tempo: None,
+            row_speed: None,
+            cut: false,
+            released: false,
+            pitch,
+            sampler,
+            effects: vec![],
+            panning: 0.0,
+            volume: 1.0,
+            paused: false,
+            fade: None,
+            glide: None,
+            volume_envelope: EnvelopeState::new(),
+            pitch_envelope: EnvelopeState::new(),
+            pan_envelope: EnvelopeState::new(),
+            frame: 0,
+            controls: Controls::default(),
+            scratch: (vec![], vec![]),
+        }
+    }
+
+    pub fn from_instruction(data: Arc<Project>, ins: &common::NoteInstruction) -> Self {
+        let instrument = &data.instruments[ins.instrument];
+
+        let (sample, base_pitch, mode) = match instrument.zone_for(ins.pitch, ins.volume) {
+            Some(zone) => (zone.sample, zone.base_pitch, zone.mode.clone()),
+            None => (
+                instrument.sample,
+                instrument.base_pitch,
+                instrument.mode.clone(),
+            ),
+        };
+
+        let sampler = mode.new_sampler(data.clone(), sample, ins.start);
+
+        Self {
+            data,
+            instrument: ins.instrument,
+            sample,
+            mode,
+            start: ins.start,
+            base_pitch,
+            tempo: None,
+            row_speed: None,
+            cut: false,
+            released: false,
+            pitch: ins.pitch,
+            sampler,
+            effects: ins
+                .effects
+                .iter()
+                .cloned()
+                .map(EffectState::new)
+                .collect::<_>(),
+            panning: ins.pan,
+            volume: ins.volume,
+            paused: false,
+            fade: None,
+            glide: None,
+            volume_envelope: EnvelopeState::new(),
+            pitch_envelope: EnvelopeState::new(),
+            pan_envelope: EnvelopeState::new(),
+            frame: 0,
+            controls: Controls::default(),
+            scratch: (vec![], vec![]),
+        }
+    }
+
+    /// Releases the note, letting the sampler play out past its sustain loops.
+    pub fn stop(&mut self) {
+        self.released = true;
+        self.sampler.release();
+        self.volume_envelope.release();
+        self.pitch_envelope.release();
+        self.pan_envelope.release();
+    }
+
+    /// Fades the channel out linearly over the given amount of seconds.
+    ///
+    /// The fade applies on top of the volume envelope, which keeps sustaining meanwhile.
+    pub fn fade(&mut self, amount_secs: f64) {
+        // a new fade picks up from wherever an ongoing one got to
+        let from = self.fade.map_or(1.0, |fade| fade.gain_at(0.0));
+
+        self.fade = Some(FadeState {
+            from,
+            elapsed: 0.0,
+            length: amount_secs.max(0.0),
+        });
+    }
+
+    /// Glides this channel's note to the pitch of another, instead of retriggering it.
+    ///
+    /// Returns false if the note is for a different instrument, in which case it cannot glide.
+    pub fn glide_to(&mut self, ins: &NoteInstruction, secs: f64) -> bool {
+        if ins.instrument != self.instrument {
+            return false;
+        }
+
+        self.volume = ins.volume;
+        self.panning = ins.pan;
+        self.add_effects(ins);
+
+        // the glide starts from wherever an ongoing one got to
+        let from = self.pitch + self.glide.map_or(0.0, |glide| glide.offset());
+
+        self.pitch = ins.pitch;
+        self.glide = (secs > 0.0).then_some(GlideState {
+            offset: from - ins.pitch,
+            length: secs,
+            frames: 0,
+            pos: 0.0,
+        });
+
+        true
+    }
+
+    /// Restarts the note's sample from where it started, keeping everything else as it is.
+    ///
+    /// A released note stays released, so that it does not go back into its sustain loops.
+    fn retrigger(&mut self) {
+        self.sampler = self
+            .mode
+            .new_sampler(self.data.clone(), self.sample, self.start);
+
+        if self.released {
+            self.sampler.release();
+        }
+
+        if let Some(tempo) = self.tempo {
+            self.sampler.set_tempo(tempo);
+        }
+    }
+
+    /// Whether the channel went silent for good and can be freed.
+    pub fn finished(&self) -> bool {
+        self.cut
+            || self.sampler.finished()
+            || self.fade.is_some_and(|fade| fade.done())
+            || self.envelope_silent()
+    }
+
+    fn envelope_silent(&self) -> bool {
+        match &self.get_instrument().volume_envelope {
+            Some(env) => {
+                self.volume_envelope.ended(env)
+                    && self
+                        .volume_envelope
+                        .value(env)
+                        .is_some_and(|value| value <= 0.0)
+            }
+            None => false,
+        }
+    }
+
+    /// Sets the tempo of the track, for samplers that follow it.
+    pub fn set_tempo(&mut self, tempo: f64) {
+        self.tempo = Some(tempo);
+        self.sampler.set_tempo(tempo);
+    }
+
+    /// Sets how many rows per second the pattern the channel plays in goes through, for effects
+    /// timed in rows.
+    pub fn set_row_speed(&mut self, row_speed: f64) {
+        self.row_speed = Some(row_speed);
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn add_effects(&mut self, instruction: &NoteInstruction) {
+        for effect in &instruction.effects {
+            self.effects.push(EffectState::new(effect.clone()));
+        }
+    }
+
+    /// Frames until the next retrigger or note cut, if any.
+    fn next_event(&self, rate: f64) -> Option<usize> {
+        self.effects
+            .iter()
+            .filter_map(|state| Some(state.next_event(rate, self.row_speed)? - state.frames))
+            .min()
+    }
+
+    fn advance_effects(&mut self, frames: usize, rate: f64) {
+        let row_speed = self.row_speed;
+        let mut retriggers = vec![];
+        let mut cut = false;
+        let mut slides = 0.0;
+
+        self.effects.retain_mut(|state| {
+            // blocks are split at every event, so at most one is due by the end of this one
+            let due = state
+                .next_event(rate, row_speed)
+                .is_some_and(|at| at <= state.frames + frames);
+            let expired = state.advance(frames, rate);
+
+            match &state.def.effect {
+                Effect::Retrigger(retrigger) if due => retriggers.push(retrigger.volume),
+                Effect::NoteCut(_) if due => cut = true,
+                // a slide leaves the pitch where it got to by the end of the effect
+                Effect::Portamento(portamento) if expired => {
+                    slides += portamento.amount * slid(portamento, state.def.length)
+                }
+                _ => {}
+            }
+
+            !expired
+        });
+
+        self.pitch += slides;
+
+        for volume in &retriggers {
+            self.retrigger();
+
+            if let Some(volume) = volume {
+                self.volume *= volume;
+            }
+        }
+
+        // a retriggered note starts at its new volume, rather than at the next control period
+        if !retriggers.is_empty() {
+            self.controls = self.controls();
+        }
+
+        self.cut |= cut;
+    }
+
+    fn advance_glide(&mut self, frames: usize, rate: f64) {
+        if self
+            .glide
+            .as_mut()
+            .is_some_and(|glide| glide.advance(frames, rate))
+        {
+            self.glide = None;
+        }
+    }
+
+    /// The parameters of the channel at this point, with the offsets of its effects and glide.
+    fn controls(&self) -> Controls {
+        let instrument = self.get_instrument();
+        let (mut pitch, mut volume, mut panning) = (self.pitch, self.volume, self.panning);
+
+        for effect in &self.effects {
+            let (d_pitch, d_volume, d_panning) = effect.modulation();
+            pitch += d_pitch;
+            volume += d_volume;
+            panning += d_panning;
+        }
+
+        if let Some(glide) = &self.glide {
+            pitch += glide.offset();
+        }
+
+        if let Some(env) = &instrument.pitch_envelope {
+            pitch += self.pitch_envelope.value(env).unwrap_or(0.0);
+        }
+
+        Controls {
+            pitch_rate: 2.0_f64.powf((pitch - self.base_pitch) / 12.0),
+            volume: volume * instrument.volume,
+            panning: panning + instrument.pan,
+        }
+    }
+
+    fn render_chunk(
+        &mut self,
+        mut left_sink: AudioBufferSlice<'_>,
+        mut right_sink: AudioBufferSlice<'_>,
+    ) {
+        let data = self.data.clone();
+        let instrument = &data.instruments[self.instrument];
+        let rate = left_sink.rate;
+        let len_secs = left_sink.len_secs();
+
+        // parameters are held from the start of each control period, wherever it is split
+        if self.frame.is_multiple_of(CONTROL_FRAMES) {
+            self.controls = self.controls();
+        }
+
+        let Controls {
+            pitch_rate,
+            volume,
+            panning,
+        } = self.controls;
+
+        if let Some(env) = &instrument.pitch_envelope {
+            self.pitch_envelope.advance(env, len_secs);
+        }
+
+        let stereo = data.samples[self.sample].is_stereo();
+
+        // the sampler renders its stereo image once, which is then panned into both sides
+        let len = left_sink.len();
+        let (scratch_left, scratch_right) = &mut self.scratch;
+
+        scratch_left.clear();
+        scratch_left.resize(len, 0.0);
+        scratch_right.clear();
+        scratch_right.resize(len, 0.0);
+
+        self.sampler.set_pitch_rate(pitch_rate);
+        self.sampler.render(
+            AudioBufferSlice::new(scratch_left, rate / pitch_rate, left_sink.resampler),
+            AudioBufferSlice::new(scratch_right, rate / pitch_rate, left_sink.resampler),
+            volume,
+        );
+
+        if let Some(env) = &instrument.volume_envelope {
+            self.volume_envelope
+                .apply_gain(env, scratch_left, scratch_right, rate);
+        }
+
+        if let Some(fade) = &mut self.fade {
+            for (i, (l, r)) in scratch_left
+                .iter_mut()
+                .zip(scratch_right.iter_mut())
+                .enumerate()
+            {
+                let gain = fade.gain_at(i as f64 / rate);
+                *l *= gain;
+                *r *= gain;
+            }
+
+            fade.elapsed += len_secs;
+        }
+
+        match &instrument.pan_envelope {
+            Some(env) => {
+                for (i, (l, r)) in scratch_left.iter().zip(scratch_right.iter()).enumerate() {
+                    let (left_gain, right_gain) = pan_gains(
+                        panning + self.pan_envelope.value(env).unwrap_or(0.0),
+                        stereo,
+                    );
+                    self.pan_envelope.advance(env, 1.0 / rate);
+
+                    left_sink.out[i] += l * left_gain;
+                    right_sink.out[i] += r * right_gain;
+                }
+            }
+            None => {
+                let (left_gain, right_gain) = pan_gains(panning, stereo);
+                left_sink.add_scaled(scratch_left, left_gain);
+                right_sink.add_scaled(scratch_right, right_gain);
+            }
+        }
+
+        self.frame += left_sink.len();
+    }
+
+    /// Renders the channel into the given sinks, mixing it with what is already in them.
+    ///
+    /// Returns false once the channel went silent for good.
+    pub(crate) fn render(
+        &mut self,
+        mut left_sink: AudioBufferSlice<'_>,
+        mut right_sink: AudioBufferSlice<'_>,
+    ) -> bool {
+        if self.paused {
+            return true;
+        }
+
+        debug_assert!(left_sink.rate == right_sink.rate);
+        debug_assert!(left_sink.len() == right_sink.len());
+
+        // parameters are modulated at control rate, in chunks counted from the start of the note,
+        // which are also split wherever a retrigger or cut happens so that it lands on its frame
+        let rate = left_sink.rate;
+        let len = left_sink.len();
+        let mut from = 0;
+
+        while from < len && !self.cut {
+            let mut to = len.min(from + CONTROL_FRAMES - self.frame % CONTROL_FRAMES);
+
+            if let Some(event) = self.next_event(rate) {
+                to = to.min(from + event);
+            }
+
+            self.render_chunk(left_sink.window(from, to), right_sink.window(from, to));
+            self.advance_effects(to - from, rate);
+            self.advance_glide(to - from, rate);
+            from = to;
+        }
+
+        !self.finished()
+    }
+
+    pub fn next_loop(&mut self) -> bool {
+        self.sampler.next_loop()
+    }
+}
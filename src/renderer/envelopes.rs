@@ -0,0 +1,61 @@
+use crate::common::*;
+
+/// Playback state of an instrument envelope for a single note.
+#[derive(Clone, Copy, Default)]
+pub struct EnvelopeState {
+    time: f64,
+    released: bool,
+}
+
+impl EnvelopeState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn release(&mut self) {
+        self.released = true;
+    }
+
+    pub fn value(&self, env: &Envelope) -> Option<f64> {
+        env.value_at(self.time)
+    }
+
+    pub fn advance(&mut self, env: &Envelope, secs: f64) {
+        self.time += secs;
+
+        let Some(section) = env.active_loop(self.released) else {
+            return;
+        };
+
+        let (Some(from), Some(to)) = (env.points.get(section.from), env.points.get(section.to))
+        else {
+            return;
+        };
+
+        if self.time >= to.time {
+            let len = to.time - from.time;
+
+            self.time = if len > 0.0 {
+                from.time + (self.time - to.time) % len
+            } else {
+                to.time
+            };
+        }
+    }
+
+    /// Whether the envelope went past its last point and will not loop back.
+    pub fn ended(&self, env: &Envelope) -> bool {
+        env.active_loop(self.released).is_none() && self.time >= env.len()
+    }
+
+    /// Multiplies each stereo frame by the envelope as it goes, one frame every `1 / rate`
+    /// seconds.
+    pub fn apply_gain(&mut self, env: &Envelope, left: &mut [f64], right: &mut [f64], rate: f64) {
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            let gain = self.value(env).unwrap_or(1.0);
+            *l *= gain;
+            *r *= gain;
+            self.advance(env, 1.0 / rate);
+        }
+    }
+}
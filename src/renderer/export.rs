@@ -0,0 +1,208 @@
+//! Offline rendering of tracks into WAV files.
+
+use crate::common::*;
+use crate::renderer::*;
+use std::fmt;
+use std::io::{Seek, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WavFormat {
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl WavFormat {
+    fn spec(&self, rate: u32) -> hound::WavSpec {
+        let (bits_per_sample, sample_format) = match self {
+            Self::Int16 => (16, hound::SampleFormat::Int),
+            Self::Int24 => (24, hound::SampleFormat::Int),
+            Self::Float32 => (32, hound::SampleFormat::Float),
+        };
+
+        hound::WavSpec {
+            channels: 2,
+            sample_rate: rate,
+            bits_per_sample,
+            sample_format,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderOptions {
+    pub rate: u32,
+    pub format: WavFormat,
+    pub block_len: usize,
+}
+
+impl RenderOptions {
+    /// Checks that the options can render anything at all.
+    pub fn validate(&self) -> Result<(), RenderError> {
+        if self.rate == 0 {
+            Err(RenderError::InvalidOptions("the rate must be above 0"))
+        } else if self.block_len == 0 {
+            Err(RenderError::InvalidOptions(
+                "the block length must be above 0",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            rate: 48000,
+            format: WavFormat::Int16,
+            block_len: 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RenderError {
+    NoSuchTrack(usize),
+    /// A pattern of the track has no rows per second, and would never end.
+    InvalidRowSpeed(usize),
+    InvalidOptions(&'static str),
+    Wav(hound::Error),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoSuchTrack(which) => write!(f, "project has no track #{}", which),
+            Self::InvalidRowSpeed(which) => {
+                write!(f, "pattern #{} needs a row speed above 0", which)
+            }
+            Self::InvalidOptions(why) => write!(f, "invalid render options: {}", why),
+            Self::Wav(err) => write!(f, "could not write WAV: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl From<hound::Error> for RenderError {
+    fn from(err: hound::Error) -> Self {
+        Self::Wav(err)
+    }
+}
+
+/// Checks that a track exists and that the options can render it, before anything is written.
+fn check_render(data: &Project, track: usize, options: &RenderOptions) -> Result<(), RenderError> {
+    let Some(this_track) = data.tracks.get(track) else {
+        return Err(RenderError::NoSuchTrack(track));
+    };
+
+    for pref in &this_track.pattern_refs {
+        let row_speed = data.patterns[pref.pattern].row_speed;
+
+        if row_speed.is_nan() || row_speed <= 0.0 {
+            return Err(RenderError::InvalidRowSpeed(pref.pattern));
+        }
+    }
+
+    options.validate()
+}
+
+/// Renders a track block by block until it ends, handing each stereo block to `on_block`.
+///
+/// The last block is cut short at the frame the track ends on.
+pub fn render_track<F>(
+    data: Arc<Project>,
+    track: usize,
+    options: &RenderOptions,
+    on_block: F,
+) -> Result<(), RenderError>
+where
+    F: FnMut(&[f64], &[f64]) -> Result<(), RenderError>,
+{
+    check_render(&data, track, options)?;
+    render_blocks(data, track, options, on_block)
+}
+
+fn render_blocks<F>(
+    data: Arc<Project>,
+    track: usize,
+    options: &RenderOptions,
+    mut on_block: F,
+) -> Result<(), RenderError>
+where
+    F: FnMut(&[f64], &[f64]) -> Result<(), RenderError>,
+{
+    let resampler = CubicResampler;
+    let rate = options.rate as f64;
+    let mut left = AudioBuffer::new(options.block_len, rate);
+    let mut right = AudioBuffer::new(options.block_len, rate);
+
+    let mut state = RenderState::new(data);
+    state.set_track(track);
+
+    while !state.finished() {
+        let len = state.frames_left(rate).min(options.block_len);
+
+        state.render(left.slice(&resampler), right.slice(&resampler));
+        on_block(&left.data[..len], &right.data[..len])?;
+    }
+
+    Ok(())
+}
+
+/// Renders a track and writes it as a stereo WAV stream.
+pub fn write_track_wav<W: Write + Seek>(
+    data: Arc<Project>,
+    track: usize,
+    writer: W,
+    options: &RenderOptions,
+) -> Result<(), RenderError> {
+    // nothing is written for a track that cannot render
+    check_render(&data, track, options)?;
+    write_wav(data, track, writer, options)
+}
+
+fn write_wav<W: Write + Seek>(
+    data: Arc<Project>,
+    track: usize,
+    writer: W,
+    options: &RenderOptions,
+) -> Result<(), RenderError> {
+    // rounded and clipped on the same scale integer samples are read at
+    let quantize = |x: f64, scale: f64| (x * scale).round().clamp(-scale, scale - 1.0);
+    let mut wav = hound::WavWriter::new(writer, options.format.spec(options.rate))?;
+
+    render_blocks(data, track, options, |left, right| {
+        for (l, r) in left.iter().zip(right) {
+            for x in [*l, *r] {
+                match options.format {
+                    WavFormat::Int16 => wav.write_sample(quantize(x, 32768.0) as i16)?,
+                    WavFormat::Int24 => wav.write_sample(quantize(x, 8388608.0) as i32)?,
+                    WavFormat::Float32 => wav.write_sample(x as f32)?,
+                }
+            }
+        }
+
+        Ok(())
+    })?;
+
+    wav.finalize()?;
+    Ok(())
+}
+
+/// Renders a track into a stereo WAV file.
+pub fn render_track_to_wav<P: AsRef<Path>>(
+    data: Arc<Project>,
+    track: usize,
+    path: P,
+    options: &RenderOptions,
+) -> Result<(), RenderError> {
+    // no file is created for a track that cannot render
+    check_render(&data, track, options)?;
+
+    let file = std::io::BufWriter::new(std::fs::File::create(path).map_err(hound::Error::from)?);
+    write_wav(data, track, file, options)
+}
@@ -1,3 +1,13 @@
+pub mod channels;
+pub mod envelopes;
+pub mod export;
+pub mod patterns;
 pub mod samplers;
+pub mod tracks;
 
+pub use channels::*;
+pub use envelopes::*;
+pub use export::*;
+pub use patterns::*;
 pub use samplers::*;
+pub use tracks::*;
@@ -0,0 +1,230 @@
+use crate::common::*;
+use crate::renderer::*;
+use std::sync::Arc;
+
+pub struct PatternState {
+    data: Arc<Project>,
+    pattern: usize,
+    row: usize,
+    row_speed: f64,   // rows per second
+    row_frame: usize, // frames already rendered of the current row
+    channels: Vec<Option<ChannelState>>,
+    tempo: Option<f64>, // beats per minute, if playing in a track
+}
+
+impl PatternState {
+    pub fn new(data: Arc<Project>, pattern: usize) -> Self {
+        let mut channels: Vec<Option<ChannelState>> = vec![];
+
+        for _ in 0..data.patterns[pattern].width {
+            channels.push(None);
+        }
+
+        let row_speed = data.patterns[pattern].row_speed;
+
+        Self {
+            data,
+            pattern,
+            channels,
+            row: 0,
+            row_speed,
+            row_frame: 0,
+            tempo: None,
+        }
+    }
+
+    /// Sets the tempo the pattern plays at, for instruments that follow it.
+    pub fn set_tempo(&mut self, tempo: f64) {
+        self.tempo = Some(tempo);
+
+        for channel in self.channels.iter_mut().flatten() {
+            channel.set_tempo(tempo);
+        }
+    }
+
+    fn get_pattern(&self) -> &Pattern {
+        &self.data.patterns[self.pattern]
+    }
+
+    pub fn curr_instructions(&self) -> &[Instruction] {
+        let pattern = self.get_pattern();
+        let width = pattern.width as usize;
+
+        &pattern.instructions[self.row * width..(self.row + 1) * width]
+    }
+
+    pub fn channels(&mut self) -> &mut Vec<Option<ChannelState>> {
+        &mut self.channels
+    }
+
+    pub fn finished(&self) -> bool {
+        self.row >= self.get_pattern().height as usize
+    }
+
+    /// The frame, counted from the start of the pattern, at which a row starts.
+    ///
+    /// Row boundaries are always derived from the start of the pattern rather than accumulated,
+    /// so that they land on the same frames regardless of how the output is split in blocks.
+    fn row_start_frame(&self, row: usize, rate: f64) -> usize {
+        (row as f64 / self.row_speed * rate).round() as usize
+    }
+
+    /// How many frames of the pattern are left to play, at a rate.
+    pub fn frames_left(&self, rate: f64) -> usize {
+        let end = self.row_start_frame(self.get_pattern().height as usize, rate);
+
+        end.saturating_sub(self.row_start_frame(self.row, rate) + self.row_frame)
+    }
+
+    /// The frame, counted from the start of the current row, at which a note delayed by a
+    /// fraction of the row starts.
+    fn delay_frame(&self, fraction: f64, rate: f64) -> usize {
+        let row_start = self.row_start_frame(self.row, rate);
+        ((self.row as f64 + fraction) / self.row_speed * rate).round() as usize - row_start
+    }
+
+    /// Applies the instructions of the current row that are due at the current frame of it.
+    fn apply_row(&mut self, rate: f64) {
+        let width = self.get_pattern().width as usize;
+        let row_idx_start = width * self.row;
+        let data = self.data.clone();
+        let row = &data.patterns[self.pattern].instructions[row_idx_start..row_idx_start + width];
+
+        for (channel, instruction) in row.iter().enumerate() {
+            let due = match instruction {
+                Instruction::Note(note_ins) if note_ins.delay() > 0.0 => {
+                    self.delay_frame(note_ins.delay(), rate) == self.row_frame
+                }
+                _ => self.row_frame == 0,
+            };
+
+            if due {
+                self.apply_instruction(channel, instruction);
+            }
+        }
+    }
+
+    /// The frame, counted from the start of the current row, of the next delayed note after the
+    /// current frame.
+    fn next_delayed_frame(&self, rate: f64) -> Option<usize> {
+        self.curr_instructions()
+            .iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::Note(note_ins) if note_ins.delay() > 0.0 => {
+                    Some(self.delay_frame(note_ins.delay(), rate))
+                }
+                _ => None,
+            })
+            .filter(|frame| *frame > self.row_frame)
+            .min()
+    }
+
+    fn apply_instruction(&mut self, channel: usize, instruction: &Instruction) {
+        let channel = &mut self.channels[channel];
+
+        use Instruction::*;
+        match instruction {
+            None => {}
+            Cut => {
+                *channel = Option::None;
+            }
+            Stop => {
+                if let Some(channel) = channel {
+                    channel.stop();
+                }
+            }
+            NextLoop => {
+                if let Some(state) = channel {
+                    if !state.next_loop() {
+                        *channel = Option::None;
+                    }
+                }
+            }
+            Fade(num) => {
+                if let Some(channel) = channel {
+                    channel.fade(*num);
+                }
+            }
+            Pause => {
+                if let Some(channel) = channel {
+                    channel.toggle_pause();
+                }
+            }
+            Note(note_ins) => {
+                let glided = match (channel.as_mut(), note_ins.glide_time()) {
+                    (Some(state), Some(secs)) => state.glide_to(note_ins, secs),
+                    _ => false,
+                };
+
+                if !glided {
+                    let mut state = ChannelState::from_instruction(self.data.clone(), note_ins);
+                    state.set_row_speed(self.row_speed);
+
+                    if let Some(tempo) = self.tempo {
+                        state.set_tempo(tempo);
+                    }
+
+                    *channel = Some(state);
+                }
+            }
+        }
+    }
+
+    fn render_channels(
+        &mut self,
+        left_sink: &mut AudioBufferSlice<'_>,
+        right_sink: &mut AudioBufferSlice<'_>,
+    ) {
+        for channel in self.channels.iter_mut() {
+            if let Some(state) = channel {
+                if !state.render(left_sink.reborrow(), right_sink.reborrow()) {
+                    *channel = None;
+                }
+            }
+        }
+    }
+
+    /// Renders the pattern into the given sinks, mixing it with what is already in them.
+    ///
+    /// Returns false once the pattern has played all of its rows.
+    pub fn render(
+        &mut self,
+        mut left_sink: AudioBufferSlice<'_>,
+        mut right_sink: AudioBufferSlice<'_>,
+    ) -> bool {
+        let rate = left_sink.rate;
+        let len = left_sink.len();
+        let mut frame = 0;
+
+        while frame < len {
+            if self.finished() {
+                return false;
+            }
+
+            self.apply_row(rate);
+
+            // delayed notes split the row, so that they start on their exact frame
+            let row_len =
+                self.row_start_frame(self.row + 1, rate) - self.row_start_frame(self.row, rate);
+            let row_end = self
+                .next_delayed_frame(rate)
+                .map_or(row_len, |delayed| delayed.min(row_len));
+            let to = len.min(frame + row_end.saturating_sub(self.row_frame));
+
+            self.render_channels(
+                &mut left_sink.window(frame, to),
+                &mut right_sink.window(frame, to),
+            );
+
+            self.row_frame += to - frame;
+            frame = to;
+
+            if self.row_frame >= row_len {
+                self.row += 1;
+                self.row_frame = 0;
+            }
+        }
+
+        !self.finished()
+    }
+}
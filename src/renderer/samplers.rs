@@ -1,6 +1,60 @@
 use crate::common;
 use crate::common::*;
-use dasp::Signal;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+
+pub trait SamplerState {
+    /// Renders the stereo image of the sampler, mixing it with what is already in the sinks.
+    fn render(
+        &mut self,
+        left_sink: AudioBufferSlice<'_>,
+        right_sink: AudioBufferSlice<'_>,
+        gain: f64,
+    );
+
+    fn next_loop(&mut self) -> bool;
+
+    /// Moves the sampler into its release phase, e.g. by leaving sustain loops.
+    fn release(&mut self);
+
+    /// Whether the sampler will not produce any more sound.
+    fn finished(&self) -> bool;
+
+    /// Tells the sampler how fast its channel plays, relative to the sample's own pitch.
+    ///
+    /// The sinks' rate is already scaled by it, so only samplers that keep time regardless of
+    /// pitch need to know.
+    fn set_pitch_rate(&mut self, _pitch_rate: f64) {}
+
+    /// Tells the sampler the tempo of the track it plays in, in beats per minute.
+    fn set_tempo(&mut self, _tempo: f64) {}
+}
+
+/// A linear crossfade across the jump of a forward loop.
+#[derive(Clone, Copy)]
+struct LoopCrossfade {
+    /// Position at which the crossfade starts.
+    start: f64,
+    /// Length of the crossfade, negative when playing backwards.
+    length: f64,
+    /// Offset from the audio that fades out to the one that fades in.
+    shift: f64,
+}
+
+impl LoopCrossfade {
+    /// How far along the crossfade a position is, from 0 where it starts to 1 at the jump, and 0
+    /// anywhere else.
+    fn progress(&self, at: f64) -> f64 {
+        let progress = (at - self.start) / self.length;
+
+        if (0.0..=1.0).contains(&progress) {
+            progress
+        } else {
+            0.0
+        }
+    }
+}
 
 pub struct BasicSamplerState {
     data: Arc<Project>,
@@ -8,6 +62,11 @@ pub struct BasicSamplerState {
     sample: usize,
     position: Position,
     curr_loop: usize,
+    passes: u32, // times playback went back around the current loop
+    released: bool,
+    /// A backward loop that was left while going through it, which plays out to its start
+    /// before playback carries on forward from its end.
+    leaving: Option<LoopSection>,
 }
 
 impl BasicSamplerState {
@@ -15,203 +74,735 @@ impl BasicSamplerState {
         &self.data.samples[self.sample]
     }
 
-    pub fn new(data: Arc<Project>, sample: usize, def: common::BasicMode) -> Self {
-        let start = def.start;
+    pub fn new(
+        data: Arc<Project>,
+        sample: usize,
+        def: common::BasicMode,
+        start: NoteStart,
+    ) -> Self {
+        let duration = data.samples[sample].duration();
+        let at = match start.offset {
+            Some(offset) => offset.secs(duration),
+            None if start.reverse => duration,
+            None => def.start,
+        };
+
         Self {
             data,
             sample,
             def,
             position: Position {
-                at: start,
-                reversing: false,
+                at,
+                reversing: start.reverse,
             },
             curr_loop: 0,
+            passes: 0,
+            released: false,
+            leaving: None,
         }
     }
 
-    pub fn next_loop(&mut self) {
-        self.curr_loop += 1;
-    }
-
     fn this_loop(&self) -> Option<&LoopDef> {
-        self.def.loops.get(self.curr_loop)
+        if self.released {
+            return None;
+        }
+
+        // empty sections would have playback go around them forever without moving
+        self.def.loops.get(self.curr_loop).filter(|this_loop| {
+            this_loop
+                .section()
+                .is_none_or(|section| section.len() > 0.0)
+        })
     }
 
-    fn render_subseg(&self, subseg: &Subseg, sink: dasp::, offs: f64, gain: f64) {
-        let start = subseg.from.at + offs;
-        let reversing = subseg.from.reversing;
-        let length = subseg.length;
+    /// Has playback carry on past the end of the current loop once it leaves it, if it is going
+    /// backwards through a backward or ping-pong loop.
+    fn leave_loop(&mut self) {
+        match self.this_loop() {
+            Some(LoopDef::Backward(section))
+                if self.position.reversing && self.position.at >= section.from =>
+            {
+                self.leaving = Some(*section);
+            }
+            // a ping-pong loop turns around where it is, without jumping
+            Some(LoopDef::PingPong(section))
+                if self.position.reversing && self.position.at >= section.from =>
+            {
+                self.position.reversing = false;
+            }
+            _ => {}
+        }
+    }
 
-        let left = if reversing { start - length } else { start };
-        let right = if reversing { start } else { start + length };
+    /// Whether playback moves on to the next loop at the next stop, instead of going around.
+    fn last_pass(&self, section: &LoopSection) -> bool {
+        section
+            .repeats
+            .is_some_and(|repeats| self.passes >= repeats)
+    }
 
-        let left_o = (left * sink.rate) as usize;
-        let right_o = (right * sink.rate) as usize;
+    /// The crossfade of the current loop when moving in a direction, shortened to fit the audio
+    /// there is to fade in.
+    fn loop_crossfade(&self, reversing: bool) -> Option<LoopCrossfade> {
+        let section = match self.this_loop() {
+            Some(LoopDef::Forward(section)) => section,
+            Some(LoopDef::Backward(section)) if reversing => section,
+            _ => return None,
+        };
 
-        let sample = self.get_sample();
-        let left_s = (left * sample.baserate) as usize;
-        let right_s = (right * sample.baserate) as usize;
+        if self.last_pass(section) {
+            return None;
+        }
 
-        sink.resampler.resample(
-            &sample.audio[left_s..=right_s],
-            &mut sink.out[left_o..=right_o],
-            gain,
-        );
-    }
+        // forwards, the loop's end fades into what leads up to its start; backwards, its start
+        // fades into what follows its end
+        let room = if reversing {
+            self.get_sample().duration() - section.to
+        } else {
+            section.from
+        };
+        let length = section.crossfade.min(room).min(section.len());
 
-    fn subsegs(&self, from: Position, after_secs: f64) -> Vec<Subseg> {
-        let mut position = from;
-        let mut subsegs: Vec<Subseg> = vec![];
-        let mut remaining = after_secs;
+        if length <= 0.0 {
+            return None;
+        }
 
-        let this_loop = self.this_loop();
+        Some(if reversing {
+            LoopCrossfade {
+                start: section.from + length,
+                length: -length,
+                shift: section.len(),
+            }
+        } else {
+            LoopCrossfade {
+                start: section.to - length,
+                length,
+                shift: -section.len(),
+            }
+        })
+    }
 
-        if this_loop.is_none() {
-            subsegs.push(Subseg {
-                from: position,
-                length: remaining,
-            });
-            return subsegs;
+    fn render_subseg(
+        &self,
+        subseg: &Subseg,
+        sinks: [AudioBufferSlice<'_>; 2],
+        offs: f64,
+        gain: f64,
+    ) {
+        let sample = self.get_sample();
+        let crossfade = self.loop_crossfade(subseg.from.reversing);
+
+        for (mut sink, channel) in sinks.into_iter().zip(sample.stereo()) {
+            let from = sink.frame_at(offs);
+            let to = sink.frame_at(offs + subseg.length);
+
+            // the first frame of the window may fall slightly after offs
+            let skew = from as f64 / sink.rate - offs;
+            let speed = if subseg.from.reversing { -1.0 } else { 1.0 };
+            let start = subseg.from.after(skew).at;
+            let mut window = sink.window(from, to);
+
+            match crossfade {
+                None => window.mix(channel, sample.baserate, start, speed, gain),
+                Some(fade) => {
+                    let progress = |secs: f64| fade.progress(start + speed * secs);
+
+                    window.mix_shaped(channel, sample.baserate, start, speed, |secs| {
+                        gain * (1.0 - progress(secs))
+                    });
+                    window.mix_shaped(
+                        channel,
+                        sample.baserate,
+                        start + fade.shift,
+                        speed,
+                        |secs| gain * progress(secs),
+                    );
+                }
+            }
         }
+    }
 
-        let this_loop = this_loop.unwrap();
+    /// Where playback next stops to go around the current loop, or to leave it, if anywhere.
+    fn next_stop(&self) -> Option<f64> {
+        if let Some(section) = self.leaving {
+            return Some(section.from);
+        }
 
-        while remaining > 0.0 {
-            let next_stop = this_loop.next_stop(position);
+        let this_loop = self.this_loop()?;
+        let section = this_loop.section()?;
 
-            if next_stop.is_none() {
-                subsegs.push(Subseg {
-                    from: position,
-                    length: remaining,
-                });
-                return subsegs;
-            }
+        // playing backwards below a loop, e.g. from a reversed note's start, never gets back to
+        // it, and ends at the start of the sample instead of turning around there
+        if self.position.reversing && self.position.at < section.from {
+            return None;
+        }
 
-            let next_stop = next_stop.unwrap();
+        this_loop.next_stop(self.position)
+    }
 
-            let distance = (next_stop - position.at).abs();
+    /// Playback from the current position for at most `max_secs`, along with the stop of the
+    /// current loop it reaches within that time, if any.
+    fn next_subseg(&self, max_secs: f64) -> (Subseg, Option<f64>) {
+        match self.next_stop() {
+            Some(stop) if (stop - self.position.at).abs() < max_secs => (
+                Subseg {
+                    from: self.position,
+                    length: (stop - self.position.at).abs(),
+                },
+                Some(stop),
+            ),
+            _ => (
+                Subseg {
+                    from: self.position,
+                    length: max_secs,
+                },
+                None,
+            ),
+        }
+    }
 
-            subsegs.push(Subseg {
-                from: position,
-                length: remaining.min(distance),
-            });
+    /// Goes around the current loop from the stop playback is at, or moves on to the next loop
+    /// once it went around enough times.
+    fn loop_around(&mut self) {
+        if let Some(section) = self.leaving.take() {
+            self.position = Position {
+                at: section.to,
+                reversing: false,
+            };
+            return;
+        }
 
-            if remaining <= distance {
-                return subsegs;
+        let Some(this_loop) = self.this_loop().copied() else {
+            return;
+        };
+        let section = this_loop.section().unwrap();
+
+        // stopping at the start of the sample, or turning into a backward loop, is not a pass
+        let at_loop_point = self.position.at == section.from || self.position.at == section.to;
+        let entering = matches!(this_loop, LoopDef::Backward(_)) && !self.position.reversing;
+
+        if at_loop_point && !entering {
+            if self.last_pass(&section) {
+                // a backward loop is left from its end, and a ping-pong one bounces once more,
+                // so that playback carries on forward into what follows them
+                match this_loop {
+                    LoopDef::Backward(_) if self.position.reversing => {
+                        self.position = Position {
+                            at: section.to,
+                            reversing: false,
+                        };
+                    }
+                    LoopDef::PingPong(_) if self.position.reversing => {
+                        self.position.reversing = false;
+                    }
+                    _ => {}
+                }
+
+                self.curr_loop += 1;
+                self.passes = 0;
+                return;
             }
 
-            remaining -= distance;
-            position = this_loop.next_start(position).unwrap();
+            self.passes += 1;
         }
 
-        unreachable!()
-    }
-
-    fn set_position_after(&mut self, subseg: &Subseg) {
-        self.position = Position {
-            reversing: subseg.from.reversing,
-            at: subseg.from.at + subseg.length * (if subseg.from.reversing { -1.0 } else { 1.0 }),
-        };
+        self.position = this_loop.next_start(self.position).unwrap();
     }
 }
 
 impl SamplerState for BasicSamplerState {
-    fn render(&mut self, sink: AudioBufferSlice<'_>, gain: f64) {
+    fn render(
+        &mut self,
+        mut left_sink: AudioBufferSlice<'_>,
+        mut right_sink: AudioBufferSlice<'_>,
+        gain: f64,
+    ) {
         let mut render_offs: f64 = 0.0;
-        let length = sink.len_samples();
+        let mut remaining = left_sink.len_secs();
+
+        // loops may change along the way, so each subseg is rendered before the next is found
+        loop {
+            let (subseg, stop) = self.next_subseg(remaining);
+
+            self.render_subseg(
+                &subseg,
+                [left_sink.reborrow(), right_sink.reborrow()],
+                render_offs,
+                gain,
+            );
+            render_offs += subseg.length;
+            remaining -= subseg.length;
+            self.position = subseg.end();
 
-        let subsegs = self.subsegs(self.position, length);
+            let Some(stop) = stop else {
+                break;
+            };
 
-        for subseg in &subsegs {
-            self.render_subseg(&subseg, sink, render_offs, gain);
-            render_offs += subseg.length;
+            self.position.at = stop;
+            self.loop_around();
         }
-
-        self.set_position_after(subsegs.last().unwrap());
     }
 
     fn next_loop(&mut self) -> bool {
         if self.curr_loop + 1 < self.def.loops.len() {
+            self.leave_loop();
             self.curr_loop += 1;
+            self.passes = 0;
             true
         } else {
             false
         }
     }
+
+    fn release(&mut self) {
+        self.leave_loop();
+        self.released = true;
+    }
+
+    fn finished(&self) -> bool {
+        if self.next_stop().is_some() {
+            return false;
+        }
+
+        if self.position.reversing {
+            self.position.at <= 0.0
+        } else {
+            self.position.at >= self.get_sample().duration()
+        }
+    }
+}
+
+/// Granules are never spawned closer together than this, in seconds.
+const MIN_GRANULE_INTERVAL: f64 = 0.001;
+
+/// A random value between `-amount` and `amount`.
+fn spread(rng: &mut StdRng, amount: f64) -> f64 {
+    if amount > 0.0 {
+        rng.gen_range(-amount..=amount)
+    } else {
+        0.0
+    }
 }
 
-struct GranuleState {
+pub struct GranuleState {
     pub at: f64,
     pub age: f64,
     pub volume: f64,
+    pub speed: f64,
+    pub pan: f64,
 }
 
 impl GranuleState {
-    pub fn new(&mut self, at: f64, age: f64, volume: f64) -> Self {
-        Self { at, age, volume }
+    pub fn new(at: f64, age: f64, volume: f64) -> Self {
+        Self {
+            at,
+            age,
+            volume,
+            speed: 1.0,
+            pan: 0.0,
+        }
     }
 
     pub fn advance(&mut self, amount: f64) {
         self.age += amount;
-        self.at += amount;
+        self.at += amount * self.speed;
     }
 
-    fn volume(&self, def: &'static GranulatingMode) -> f64 {
-        let position = self.age / def.segment.len();
-        let dist = f64::min(1.0 - position, position);
+    /// The window of a granule at a given age, which is zero outside of its lifetime.
+    fn window(def: &GranulatingMode, age: f64) -> f64 {
+        def.smoothing.window(age / def.segment.len())
+    }
 
-        use common::SmoothingMode::*;
-        match &def.smoothing {
-            None => 1.0,
-            Triangle => dist * 2.0,
-            Linear(width) => dist / width,
-            SquareRoot(width) => (dist / width).sqrt(),
-            Cosine(width) => ((1.0 - dist) * std::f64::consts::PI / width / 2.0).cos(),
-        }
+    pub fn volume(&self, def: &GranulatingMode) -> f64 {
+        Self::window(def, self.age)
+    }
+
+    pub fn expired(&self, def: &GranulatingMode) -> bool {
+        self.age >= def.segment.len()
     }
 
     pub fn render(
         &mut self,
         sample: &Sample,
         def: &GranulatingMode,
-        sink: AudioBufferSlice<'_>,
+        left_sink: AudioBufferSlice<'_>,
+        right_sink: AudioBufferSlice<'_>,
         gain: f64,
     ) {
-        // WIP
+        self.render_shaped(sample, def, left_sink, right_sink, &|_| gain);
+    }
+
+    /// Renders the granule with a gain that varies over the seconds since the start of the
+    /// sinks.
+    fn render_shaped(
+        &mut self,
+        sample: &Sample,
+        def: &GranulatingMode,
+        left_sink: AudioBufferSlice<'_>,
+        right_sink: AudioBufferSlice<'_>,
+        gain: &dyn Fn(f64) -> f64,
+    ) {
+        let age = self.age;
+        let volume = def.gain;
+
+        self.mix(sample, left_sink, right_sink, &|secs| {
+            volume * gain(secs) * Self::window(def, age + secs)
+        });
+    }
+
+    /// Mixes the granule into the sinks at its volume and panning, scaled by `shape` over the
+    /// seconds since the start of the sinks, and advances it past them.
+    fn mix(
+        &mut self,
+        sample: &Sample,
+        left_sink: AudioBufferSlice<'_>,
+        right_sink: AudioBufferSlice<'_>,
+        shape: &dyn Fn(f64) -> f64,
+    ) {
+        let volume = self.volume;
+        let len_secs = left_sink.len_secs();
+
+        // balance panning, so centered granules play at full volume on both sides
+        let sides = [
+            (left_sink, (1.0 - self.pan).min(1.0)),
+            (right_sink, (1.0 + self.pan).min(1.0)),
+        ];
+
+        for ((mut sink, balance), channel) in sides.into_iter().zip(sample.stereo()) {
+            sink.mix_shaped(channel, sample.baserate, self.at, self.speed, |secs| {
+                volume * balance * shape(secs)
+            });
+        }
+
+        self.advance(len_secs);
     }
 }
 
-pub struct GranulatingSamplerState {
-    data: Arc<Project>,
-    sample: usize,
+/// Crossfade of a granule cloud, by the cloud's age.
+#[derive(Clone, Copy)]
+struct CloudFade {
+    fade_in: f64,
+    fade_out: Option<(f64, f64)>, // age at which it started, length
+}
+
+impl CloudFade {
+    /// Equal power crossfade gain at a given age.
+    fn gain_at(&self, age: f64) -> f64 {
+        use std::f64::consts::FRAC_PI_2;
+
+        let mut gain = 1.0;
+
+        if self.fade_in > 0.0 && age < self.fade_in {
+            gain *= (age.max(0.0) / self.fade_in * FRAC_PI_2).sin();
+        }
+
+        if let Some((from, length)) = self.fade_out {
+            gain *= ((age - from).clamp(0.0, length) / length * FRAC_PI_2).cos();
+        }
+
+        gain
+    }
+}
+
+/// The granules spawned by one stage of a granulating sampler.
+struct GranuleCloud {
     def: GranulatingMode,
     granules: Vec<GranuleState>,
     age: f64,
+    next_granule: f64,
+    spawning: bool,
+    rng: StdRng,
+    fade: CloudFade,
+}
+
+impl GranuleCloud {
+    fn new(def: GranulatingMode, fade_in: f64) -> Self {
+        Self {
+            granules: vec![],
+            age: 0.0,
+            next_granule: 0.0,
+            spawning: true,
+            rng: StdRng::seed_from_u64(def.seed),
+            fade: CloudFade {
+                fade_in,
+                fade_out: None,
+            },
+            def,
+        }
+    }
+
+    /// Stops spawning granules, fading the existing ones out over `length` seconds if nonzero.
+    fn retire(&mut self, length: f64) {
+        self.spawning = false;
+
+        if length > 0.0 {
+            self.fade.fade_out = Some((self.age, length));
+        }
+    }
+
+    fn done(&self) -> bool {
+        let faded_out = self
+            .fade
+            .fade_out
+            .is_some_and(|(from, length)| self.age >= from + length);
+
+        faded_out || (!self.spawning && self.granules.is_empty())
+    }
+
+    /// Spawns the granules due before `until`.
+    ///
+    /// Granules that start partway into the next render are given a negative age, which keeps
+    /// them silent until their start time.
+    fn spawn_granules(&mut self, until: f64, duration: f64) {
+        if !self.spawning {
+            return;
+        }
+
+        let def = &self.def;
+
+        while self.next_granule < until {
+            let delay = self.next_granule - self.age;
+
+            let mut start = def.segment.from + def.scan_speed * self.next_granule;
+            if def.scan_speed != 0.0 && duration > 0.0 {
+                start = start.rem_euclid(duration);
+            }
+
+            let speed = 2.0_f64.powf(spread(&mut self.rng, def.pitch_spread) / 12.0);
+
+            self.granules.push(GranuleState {
+                // the granule reaches its start position once its delay is over
+                at: start + spread(&mut self.rng, def.position_jitter) - delay * speed,
+                age: -delay,
+                volume: 1.0,
+                speed,
+                pan: spread(&mut self.rng, def.pan_spread).clamp(-1.0, 1.0),
+            });
+
+            let jitter = 1.0 + spread(&mut self.rng, def.interval_jitter.min(1.0));
+            self.next_granule += (def.interval * jitter).max(MIN_GRANULE_INTERVAL);
+        }
+    }
+
+    fn render(
+        &mut self,
+        sample: &Sample,
+        mut left_sink: AudioBufferSlice<'_>,
+        mut right_sink: AudioBufferSlice<'_>,
+        gain: f64,
+    ) {
+        let len = left_sink.len_secs();
+        self.spawn_granules(self.age + len, sample.duration());
+
+        let age = self.age;
+        let fade = self.fade;
+        let shape = |secs: f64| gain * fade.gain_at(age + secs);
+
+        for granule in &mut self.granules {
+            granule.render_shaped(
+                sample,
+                &self.def,
+                left_sink.reborrow(),
+                right_sink.reborrow(),
+                &shape,
+            );
+        }
+
+        self.granules.retain(|granule| !granule.expired(&self.def));
+        self.age += len;
+    }
+}
+
+pub struct GranulatingSamplerState {
+    data: Arc<Project>,
+    sample: usize,
+    def: GranulatingStages,
+    curr_stage: usize,
+    clouds: Vec<GranuleCloud>,
 }
 
 impl GranulatingSamplerState {
-    pub fn new(data: Arc<Project>, sample: usize, def: common::GranulatingMode) -> Self {
+    pub fn new(data: Arc<Project>, sample: usize, def: impl Into<GranulatingStages>) -> Self {
+        let def = def.into();
+        let clouds = def
+            .stages
+            .first()
+            .map(|stage| GranuleCloud::new(stage.clone(), 0.0))
+            .into_iter()
+            .collect();
+
         Self {
             data,
             sample,
             def,
-            granules: vec![],
-            age: 0.0,
+            curr_stage: 0,
+            clouds,
         }
     }
 }
 
 impl SamplerState for GranulatingSamplerState {
     fn next_loop(&mut self) -> bool {
-        // no-op
-        // WIP: find a use for NextLopo in granulating instruments
-        //      (possibly cycling a list of GranulatingMode?)
+        let Some(stage) = self.def.stages.get(self.curr_stage + 1) else {
+            return false;
+        };
+
+        let crossfade = self.def.crossfade;
+
+        for cloud in &mut self.clouds {
+            cloud.retire(crossfade);
+        }
+
+        self.clouds
+            .push(GranuleCloud::new(stage.clone(), crossfade));
+        self.curr_stage += 1;
+        true
+    }
+
+    fn release(&mut self) {
+        for cloud in &mut self.clouds {
+            cloud.retire(0.0);
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.clouds.iter().all(|cloud| cloud.done())
+    }
+
+    fn render(
+        &mut self,
+        mut left_sink: AudioBufferSlice<'_>,
+        mut right_sink: AudioBufferSlice<'_>,
+        gain: f64,
+    ) {
+        let sample = &self.data.samples[self.sample];
+
+        for cloud in &mut self.clouds {
+            cloud.render(sample, left_sink.reborrow(), right_sink.reborrow(), gain);
+        }
+
+        self.clouds.retain(|cloud| !cloud.done());
+    }
+}
+
+/// Plays a sample through overlapping grains, laid out at the tempo and pitched separately.
+///
+/// Unlike other samplers, this one keeps time in seconds of the track rather than of the pitched
+/// sample: grains are spawned and laid out in track time, and only their playback is pitched.
+pub struct TimeStretchSamplerState {
+    data: Arc<Project>,
+    sample: usize,
+    def: TimeStretchMode,
+    tempo: f64,
+    pitch_rate: f64,
+    time: f64,
+    head: f64,
+    next_grain: f64,
+    grains: Vec<GranuleState>,
+    ended: bool,
+}
+
+impl TimeStretchSamplerState {
+    pub fn new(data: Arc<Project>, sample: usize, def: TimeStretchMode) -> Self {
+        let hop = Self::hop(&def);
+
+        Self {
+            data,
+            sample,
+            tempo: def.source_bpm,
+            head: def.start,
+            def,
+            pitch_rate: 1.0,
+            time: 0.0,
+            // the first grain is already halfway through, so that grains overlap from the start
+            // of the note; when the sample is stretched by more than it is pitched, that grain
+            // starts out reading before the start of the sample, and the note fades in over it
+            next_grain: -hop,
+            grains: vec![],
+            ended: false,
+        }
+    }
+
+    /// Time between the starts of two grains, which overlap by half.
+    fn hop(def: &TimeStretchMode) -> f64 {
+        (def.grain / 2.0).max(MIN_GRANULE_INTERVAL)
+    }
+
+    fn spawn_grains(&mut self, until: f64, duration: f64) {
+        let ratio = self.def.stretch_ratio(self.tempo);
+
+        while !self.ended && self.next_grain < until {
+            let delay = self.next_grain - self.time;
+            let at = self.head + ratio * delay;
+
+            if at >= duration {
+                self.ended = true;
+                break;
+            }
+
+            // the grain reads `at` at the moment it was due, however far into the block that is
+            let mut grain = GranuleState::new(at - delay * self.pitch_rate, -delay, 1.0);
+            grain.speed = self.pitch_rate;
+
+            self.grains.push(grain);
+            self.next_grain += Self::hop(&self.def);
+        }
+    }
+}
+
+impl SamplerState for TimeStretchSamplerState {
+    fn next_loop(&mut self) -> bool {
         false
     }
 
-    fn render(&mut self, sink: AudioBufferSlice<'_>, gain: f64) {
-        for granule in &mut self.granules {
-            granule.render(&self.data.samples[self.sample], &self.def, sink, gain);
+    // without loops, the sample just plays on until its end
+    fn release(&mut self) {}
+
+    fn finished(&self) -> bool {
+        self.ended && self.grains.is_empty()
+    }
+
+    fn set_pitch_rate(&mut self, pitch_rate: f64) {
+        self.pitch_rate = pitch_rate;
+    }
+
+    fn set_tempo(&mut self, tempo: f64) {
+        self.tempo = tempo;
+    }
+
+    fn render(
+        &mut self,
+        mut left_sink: AudioBufferSlice<'_>,
+        mut right_sink: AudioBufferSlice<'_>,
+        gain: f64,
+    ) {
+        // undo the channel's pitch scaling, to render in track time
+        let rate = left_sink.rate * self.pitch_rate;
+        let mut left_sink = left_sink.with_rate(rate);
+        let mut right_sink = right_sink.with_rate(rate);
+        let len_secs = left_sink.len_secs();
+
+        let data = self.data.clone();
+        let sample = &data.samples[self.sample];
+
+        self.spawn_grains(self.time + len_secs, sample.duration());
+
+        let length = self.def.grain;
+        let smoothing = &self.def.smoothing;
+
+        for grain in &mut self.grains {
+            let age = grain.age;
+
+            grain.mix(
+                sample,
+                left_sink.reborrow(),
+                right_sink.reborrow(),
+                &|secs| gain * smoothing.window((age + secs) / length),
+            );
         }
+
+        self.grains.retain(|grain| grain.age < length);
+        self.head += self.def.stretch_ratio(self.tempo) * len_secs;
+        self.time += len_secs;
     }
 }
@@ -0,0 +1,143 @@
+use crate::common::*;
+use crate::renderer::*;
+use std::sync::Arc;
+
+pub struct RenderState {
+    data: Arc<Project>,
+    curr_track: Option<usize>,
+    pattern_states: Vec<PatternState>,
+    next_ref: usize,
+    frame: usize,
+}
+
+impl RenderState {
+    pub fn new(data: Arc<Project>) -> Self {
+        Self {
+            data,
+            curr_track: None,
+            pattern_states: vec![],
+            next_ref: 0,
+            frame: 0,
+        }
+    }
+
+    fn get_track(&self) -> Option<&Track> {
+        self.curr_track.map(|which| &self.data.tracks[which])
+    }
+
+    /// Pattern refs of the current track, ordered by position.
+    fn sorted_refs(&self) -> Vec<PatternRef> {
+        let mut refs = self
+            .get_track()
+            .map(|track| track.pattern_refs.clone())
+            .unwrap_or_default();
+
+        refs.sort_by(|a, b| a.position.total_cmp(&b.position));
+        refs
+    }
+
+    fn add_pattern_state(&mut self, pattern: usize) {
+        let mut state = PatternState::new(self.data.clone(), pattern);
+
+        if let Some(track) = self.get_track() {
+            state.set_tempo(track.metadata.init_tempo);
+        }
+
+        self.pattern_states.push(state);
+    }
+
+    pub fn set_track(&mut self, which: usize) {
+        self.curr_track = Some(which);
+        self.pattern_states.clear();
+        self.next_ref = 0;
+        self.frame = 0;
+    }
+
+    /// Whether the current track has played all of its patterns, or there is no track at all.
+    pub fn finished(&self) -> bool {
+        match self.get_track() {
+            Some(track) => {
+                self.next_ref >= track.pattern_refs.len() && self.pattern_states.is_empty()
+            }
+            None => true,
+        }
+    }
+
+    /// How many more frames the current track plays for at a rate, until its last pattern ends.
+    pub fn frames_left(&self, rate: f64) -> usize {
+        let playing = self
+            .pattern_states
+            .iter()
+            .map(|state| state.frames_left(rate));
+        let refs = self.sorted_refs();
+        let upcoming = refs.iter().skip(self.next_ref).map(|pref| {
+            let start = (pref.position.max(0.0) * rate).round() as usize;
+            let pattern = &self.data.patterns[pref.pattern];
+            let len = (pattern.height as f64 / pattern.row_speed * rate).round() as usize;
+
+            start.saturating_sub(self.frame) + len
+        });
+
+        playing.chain(upcoming).max().unwrap_or(0)
+    }
+
+    pub fn stop(&mut self) {
+        self.curr_track = None;
+        self.pattern_states.clear();
+    }
+
+    fn render_patterns(
+        &mut self,
+        left_sink: &mut AudioBufferSlice<'_>,
+        right_sink: &mut AudioBufferSlice<'_>,
+    ) {
+        self.pattern_states
+            .retain_mut(|state| state.render(left_sink.reborrow(), right_sink.reborrow()));
+    }
+}
+
+impl StereoSource for RenderState {
+    fn render<'a>(
+        &mut self,
+        mut left_sink: AudioBufferSlice<'a>,
+        mut right_sink: AudioBufferSlice<'a>,
+    ) {
+        left_sink.out.fill(0.0);
+        right_sink.out.fill(0.0);
+
+        if self.curr_track.is_none() {
+            return;
+        }
+
+        let rate = left_sink.rate;
+        let len = left_sink.len();
+        let refs = self.sorted_refs();
+        let mut frame = 0;
+
+        while frame < len {
+            // start patterns that are due, and split the block at the next one
+            let mut to = len;
+
+            while let Some(pref) = refs.get(self.next_ref) {
+                let start = (pref.position.max(0.0) * rate).round() as usize;
+
+                if start > self.frame + frame {
+                    to = to.min(start - self.frame);
+                    break;
+                }
+
+                self.add_pattern_state(pref.pattern);
+                self.next_ref += 1;
+            }
+
+            self.render_patterns(
+                &mut left_sink.window(frame, to),
+                &mut right_sink.window(frame, to),
+            );
+
+            frame = to;
+        }
+
+        self.frame += len;
+    }
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use common::*;
+use condemus::*;
+
+/// Length of a cycle of [`periodic`], in frames.
+const PERIOD: usize = 80;
+
+/// A sine that rises through zero between the frames before and at each multiple of
+/// [`PERIOD`], and nowhere else.
+fn periodic(len: usize) -> Sample {
+    let audio: Vec<f64> = (0..len)
+        .map(|i| ((i as f64 + 0.5) * std::f64::consts::TAU / PERIOD as f64).sin())
+        .collect();
+
+    Sample::mono(audio, RATE)
+}
+
+fn frame(secs: f64) -> usize {
+    (secs * RATE).round() as usize
+}
+
+fn rough(from: usize, to: usize) -> LoopSection {
+    LoopSection {
+        crossfade: 0.01,
+        repeats: Some(3),
+        ..LoopSection::new(from as f64 / RATE, to as f64 / RATE)
+    }
+}
+
+#[test]
+fn loop_points_snap_to_rising_zero_crossings() {
+    let sample = periodic(4000);
+    let candidates = sample.find_loop_points(rough(830, 1630), &LoopSearch::default());
+
+    assert!(!candidates.is_empty());
+    assert!(candidates.len() <= 5);
+
+    for candidate in &candidates {
+        let (from, to) = (frame(candidate.section.from), frame(candidate.section.to));
+
+        assert_eq!(from % PERIOD, 0, "start at {}", from);
+        assert_eq!(to % PERIOD, 0, "end at {}", to);
+        assert!(from < to);
+        assert!(candidate.mismatch < 1e-9);
+
+        // what the rough loop had besides its points is kept
+        assert_eq!(candidate.section.crossfade, 0.01);
+        assert_eq!(candidate.section.repeats, Some(3));
+    }
+}
+
+#[test]
+fn best_loop_points_are_whole_cycles_apart() {
+    let sample = periodic(4000);
+    let search = LoopSearch {
+        zero_crossings: false,
+        candidates: 100,
+        ..LoopSearch::default()
+    };
+    let candidates = sample.find_loop_points(rough(830, 1630), &search);
+
+    assert_eq!(candidates.len(), 100);
+
+    // best first
+    for pair in candidates.windows(2) {
+        assert!(pair[0].mismatch <= pair[1].mismatch);
+    }
+
+    let best = &candidates[0];
+    let len = frame(best.section.to) - frame(best.section.from);
+    assert_eq!(len % PERIOD, 0, "loop of {} frames", len);
+    assert!(best.mismatch < 1e-9);
+
+    // loops a half cycle off line up with the opposite waveform
+    let opposite = sample.find_loop_points(
+        rough(800, 1640),
+        &LoopSearch {
+            radius: 0.0,
+            ..search
+        },
+    );
+    assert!((opposite[0].mismatch - 2.0).abs() < 1e-9);
+}
+
+#[test]
+fn loop_points_never_cross() {
+    let sample = periodic(4000);
+
+    // the rough points are closer than the radius, so that some starts come after some ends
+    let search = LoopSearch {
+        zero_crossings: false,
+        candidates: usize::MAX,
+        ..LoopSearch::default()
+    };
+    let candidates = sample.find_loop_points(rough(1000, 1020), &search);
+
+    assert!(!candidates.is_empty());
+    assert!(candidates
+        .iter()
+        .all(|candidate| candidate.section.from < candidate.section.to));
+}
+
+#[test]
+fn loop_points_stay_within_the_sample() {
+    let sample = periodic(1000);
+
+    // the search reaches before the first frame and past the last one
+    let candidates = sample.find_loop_points(rough(0, 990), &LoopSearch::default());
+    let best = &candidates[0];
+
+    assert_eq!(frame(best.section.from), PERIOD);
+    assert_eq!(frame(best.section.to), 960);
+
+    let search = LoopSearch {
+        zero_crossings: false,
+        candidates: usize::MAX,
+        ..LoopSearch::default()
+    };
+    for candidate in sample.find_loop_points(rough(0, 990), &search) {
+        assert!(frame(candidate.section.from) >= 1);
+        assert!(frame(candidate.section.to) <= 999);
+    }
+
+    // rough points past the end of the sample have nothing to snap to
+    assert!(sample
+        .find_loop_points(rough(0, 2000), &LoopSearch::default())
+        .is_empty());
+    assert!(Sample::mono(vec![0.5], RATE)
+        .find_loop_points(rough(0, 1), &LoopSearch::default())
+        .is_empty());
+}
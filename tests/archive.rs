@@ -0,0 +1,299 @@
+mod common;
+
+use common::*;
+use condemus::*;
+
+fn audio(sample: &Sample) -> Vec<f64> {
+    sample.audio.to_f64()
+}
+
+#[test]
+fn streamed_project_saves_over_the_file_it_streams_from() {
+    let file = TempFile::new("resave.zip");
+    let original = project(sine(110.0, 20000));
+
+    original.save_zip_file(&file.0).unwrap();
+    let streamed = Project::load_zip_file_streamed(&file.0, 0.0).unwrap();
+    assert!(matches!(streamed.samples[0].audio, SampleData::Streamed(_)));
+
+    streamed.save_zip_file(&file.0).unwrap();
+    streamed.save_zip_file(&file.0).unwrap();
+
+    // the streamed sample still reads the audio it was loaded with, and so does the new file
+    let loaded = Project::load_zip_file(&file.0).unwrap();
+    assert_eq!(audio(&streamed.samples[0]), audio(&original.samples[0]));
+    assert_eq!(audio(&loaded.samples[0]), audio(&original.samples[0]));
+}
+
+#[test]
+fn failed_save_leaves_the_file_alone() {
+    let source = TempFile::new("vanishing.zip");
+    let target = TempFile::new("kept.zip");
+    let original = project(sine(110.0, 1000));
+
+    original.save_zip_file(&source.0).unwrap();
+    original.save_zip_file(&target.0).unwrap();
+
+    // the streamed audio is gone before it was ever read
+    let streamed = Project::load_zip_file_streamed(&source.0, 0.0).unwrap();
+    drop(source);
+
+    assert!(streamed.save_zip_file(&target.0).is_err());
+
+    let loaded = Project::load_zip_file(&target.0).unwrap();
+    assert_eq!(audio(&loaded.samples[0]), audio(&original.samples[0]));
+}
+
+fn json<T: serde::Serialize>(value: &T) -> serde_json::Value {
+    serde_json::to_value(value).unwrap()
+}
+
+fn song() -> Project {
+    let note = Instruction::Note(NoteInstruction {
+        instrument: 0,
+        pitch: 64.0,
+        pan: -0.5,
+        volume: 0.8,
+        effects: vec![EffectInstance {
+            length: 0.5,
+            effect: Effect::TonePortamento(0.2),
+        }],
+        start: NoteStart::default(),
+    });
+
+    Project {
+        patterns: vec![Pattern {
+            instructions: vec![note, Instruction::Stop],
+            width: 1,
+            height: 2,
+            commands: vec![],
+            row_speed: 4.0,
+        }],
+        instruments: vec![Instrument {
+            sample: 1,
+            volume: 0.7,
+            pan: 0.1,
+            base_pitch: 60.0,
+            mode: InstrumentMode::Basic(BasicMode {
+                start: 0.0,
+                loops: vec![LoopDef::PingPong(LoopSection::new(0.01, 0.02))],
+            }),
+            zones: vec![],
+            volume_envelope: Some(Envelope::adsr(0.01, 0.1, 0.5, 0.2)),
+            pitch_envelope: None,
+            pan_envelope: None,
+        }],
+        tracks: vec![Track {
+            pattern_refs: vec![PatternRef {
+                position: 0.0,
+                pattern: 0,
+            }],
+            metadata: TrackMetadata {
+                name: "round trip".into(),
+                init_tempo: 140.0,
+                init_volume: 0.9,
+            },
+        }],
+        samples: vec![
+            Sample::mono(sine(110.0, 300), RATE),
+            Sample::mono(sine(220.0, 500), RATE / 2.0),
+        ],
+    }
+}
+
+#[test]
+fn project_survives_a_round_trip() {
+    let original = song();
+    let mut file = std::io::Cursor::new(vec![]);
+
+    original.save_zip(&mut file).unwrap();
+    file.set_position(0);
+    let loaded = Project::load_zip(file).unwrap();
+
+    assert_eq!(json(&loaded.patterns), json(&original.patterns));
+    assert_eq!(json(&loaded.instruments), json(&original.instruments));
+    assert_eq!(json(&loaded.tracks), json(&original.tracks));
+    assert_eq!(loaded.samples.len(), original.samples.len());
+
+    for (loaded, original) in loaded.samples.iter().zip(&original.samples) {
+        assert_eq!(loaded.baserate, original.baserate);
+        assert_eq!(audio(loaded), audio(original));
+    }
+}
+
+#[test]
+fn archive_with_a_missing_sample_is_rejected() {
+    let mut broken = song();
+    broken.samples.pop();
+
+    let mut file = std::io::Cursor::new(vec![]);
+    broken.save_zip(&mut file).unwrap();
+    file.set_position(0);
+
+    assert!(matches!(
+        Project::load_zip(file),
+        Err(ProjectFileError::CorruptEntry { .. })
+    ));
+}
+
+#[test]
+fn archive_with_a_pattern_that_never_moves_on_is_rejected() {
+    for row_speed in [0.0, -4.0, f64::NAN] {
+        let mut broken = song();
+        broken.patterns[0].row_speed = row_speed;
+
+        let mut file = std::io::Cursor::new(vec![]);
+        broken.save_zip(&mut file).unwrap();
+        file.set_position(0);
+
+        assert!(matches!(
+            Project::load_zip(file),
+            Err(ProjectFileError::CorruptEntry { .. })
+        ));
+    }
+}
+
+/// A sample with two channels that differ, in a format and layout.
+fn stereo(format: SampleFormat, layout: ChannelLayout) -> Sample {
+    let left = sine(110.0, 400);
+    let right = sine(330.0, 400);
+    let audio: Vec<f64> = left
+        .iter()
+        .zip(&right)
+        .flat_map(|(l, r)| [*l * 0.9, *r * -0.7])
+        .collect();
+
+    Sample {
+        audio: audio.into(),
+        baserate: RATE,
+        channels: 2,
+        layout: ChannelLayout::Interleaved,
+    }
+    .with_format(format)
+    .with_layout(layout)
+}
+
+#[test]
+fn sample_formats_and_layouts_survive_a_round_trip() {
+    use SampleFormat::*;
+
+    for format in [F64, F32, I16, I24] {
+        for layout in [ChannelLayout::Interleaved, ChannelLayout::Planar] {
+            let original = stereo(format, layout);
+            let project = Project {
+                patterns: vec![],
+                samples: vec![original.clone()],
+                instruments: vec![],
+                tracks: vec![],
+            };
+
+            let mut file = std::io::Cursor::new(vec![]);
+            project.save_zip(&mut file).unwrap();
+            file.set_position(0);
+            let loaded = Project::load_zip(file).unwrap().samples.remove(0);
+
+            assert_eq!(loaded.audio.format(), format);
+            assert_eq!(loaded.layout, layout);
+            assert_eq!(loaded.channels, 2);
+            assert_eq!(loaded.audio, original.audio);
+        }
+    }
+}
+
+#[test]
+fn integer_formats_keep_values_within_a_step() {
+    let values = [0.0, 0.5, -0.5, 0.999, -1.0, 0.123456];
+
+    for (format, step) in [
+        (SampleFormat::I16, 1.0 / 32768.0),
+        (SampleFormat::I24, 1.0 / 8388608.0),
+    ] {
+        let data = SampleData::from_f64(&values, format);
+        let decoded = SampleData::from_le_bytes(&data.to_le_bytes(), format).unwrap();
+
+        for (i, value) in values.iter().enumerate() {
+            assert!((decoded.get(i) - value).abs() <= step / 2.0);
+        }
+    }
+}
+
+#[test]
+fn samples_saved_as_plain_lists_still_load() {
+    let sample: Sample =
+        serde_json::from_str(r#"{"audio":[0.5,-0.25],"baserate":8000.0}"#).unwrap();
+
+    assert_eq!(sample.audio, SampleData::F64(vec![0.5, -0.25]));
+    assert_eq!(sample.channels, 1);
+    assert_eq!(sample.layout, ChannelLayout::Interleaved);
+}
+
+/// Saves a project, then rewrites the archive with each entry's bytes edited, or dropped when
+/// the edit returns None.
+fn edited_archive(project: &Project, edit: impl Fn(&str, Vec<u8>) -> Option<Vec<u8>>) -> Vec<u8> {
+    use std::io::{Read, Write};
+
+    let mut saved = std::io::Cursor::new(vec![]);
+    project.save_zip(&mut saved).unwrap();
+    saved.set_position(0);
+
+    let mut archive = zip::ZipArchive::new(saved).unwrap();
+    let mut edited = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).unwrap();
+        let name = file.name().to_owned();
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes).unwrap();
+
+        if let Some(bytes) = edit(&name, bytes) {
+            edited.start_file(name, options).unwrap();
+            edited.write_all(&bytes).unwrap();
+        }
+    }
+
+    edited.finish().unwrap().into_inner()
+}
+
+/// Loads an archive both from memory and streamed from a file.
+fn load_both_ways(bytes: Vec<u8>, name: &str) -> [Result<Project, ProjectFileError>; 2] {
+    let file = TempFile::new(name);
+    std::fs::write(&file.0, &bytes).unwrap();
+
+    [
+        Project::load_zip(std::io::Cursor::new(bytes)),
+        Project::load_zip_file_streamed(&file.0, 0.0),
+    ]
+}
+
+#[test]
+fn archive_without_the_audio_of_a_sample_is_rejected() {
+    let bytes = edited_archive(&project(sine(110.0, 1000)), |name, bytes| {
+        (name != "samples/0.raw").then_some(bytes)
+    });
+
+    for loaded in load_both_ways(bytes, "missing-audio.zip") {
+        assert!(matches!(
+            loaded,
+            Err(ProjectFileError::MissingEntry(name)) if name == "samples/0.raw"
+        ));
+    }
+}
+
+#[test]
+fn audio_that_is_not_whole_frames_is_rejected() {
+    let bytes = edited_archive(&project(sine(110.0, 1000)), |name, mut bytes| {
+        if name == "samples/0.raw" {
+            bytes.pop();
+        }
+        Some(bytes)
+    });
+
+    for loaded in load_both_ways(bytes, "truncated-audio.zip") {
+        assert!(matches!(
+            loaded,
+            Err(ProjectFileError::CorruptEntry { entry, .. }) if entry == "samples/0.raw"
+        ));
+    }
+}
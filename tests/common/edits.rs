@@ -0,0 +1,91 @@
+//! Fixtures for checking edits of a sample.
+
+use condemus::*;
+
+/// A rate that makes every tenth of a second a frame.
+pub const TENTHS: f64 = 10.0;
+
+/// The layouts and formats each edit is checked in, along with how close the values they store
+/// come to the ones they were given.
+pub const STORAGE: [(ChannelLayout, SampleFormat, f64); 4] = [
+    (ChannelLayout::Interleaved, SampleFormat::F64, 1e-12),
+    (ChannelLayout::Planar, SampleFormat::F64, 1e-12),
+    (ChannelLayout::Interleaved, SampleFormat::I16, 1e-4),
+    (ChannelLayout::Planar, SampleFormat::I24, 1e-6),
+];
+
+/// A stereo sample of 16 frames, going up on the left and down on the right.
+pub fn stereo(layout: ChannelLayout, format: SampleFormat) -> Sample {
+    let audio: Vec<f64> = (0..16)
+        .flat_map(|i| [i as f64 / 32.0, -i as f64 / 32.0])
+        .collect();
+
+    Sample {
+        audio: audio.into(),
+        baserate: TENTHS,
+        channels: 2,
+        layout: ChannelLayout::Interleaved,
+    }
+    .with_layout(layout)
+    .with_format(format)
+}
+
+pub fn channels(sample: &Sample) -> Vec<Vec<f64>> {
+    (0..sample.channels)
+        .map(|c| {
+            let channel = sample.channel(c);
+            (0..channel.len())
+                .map(|i| channel.frame(i as isize))
+                .collect()
+        })
+        .collect()
+}
+
+/// Checks an edit of the stereo sample in every layout and format, against what it should do to
+/// the audio of each channel.
+pub fn check_stereo(
+    edit: impl Fn(&Sample) -> Sample,
+    expected_left: &[f64],
+    expected_right: &[f64],
+) {
+    for (layout, format, tolerance) in STORAGE {
+        let edited = edit(&stereo(layout, format));
+
+        assert_eq!(edited.layout, layout);
+        assert_eq!(edited.audio.format(), format);
+
+        for (channel, expected) in channels(&edited)
+            .iter()
+            .zip([expected_left, expected_right])
+        {
+            assert_eq!(channel.len(), expected.len(), "{:?} {:?}", layout, format);
+
+            for (i, (x, expected)) in channel.iter().zip(expected).enumerate() {
+                assert!(
+                    (x - expected).abs() < tolerance,
+                    "{:?} {:?}: expected {} at frame {}, got {}",
+                    layout,
+                    format,
+                    expected,
+                    i,
+                    x
+                );
+            }
+        }
+    }
+}
+
+/// Checks an edit that does the same to both channels, against what it should do to the left
+/// one. The right channel is the left one negated.
+pub fn check(edit: impl Fn(&Sample) -> Sample, expected: &[f64]) {
+    check_stereo(edit, expected, &negated(expected))
+}
+
+/// Negates a channel, like the right one of the stereo sample.
+pub fn negated(audio: &[f64]) -> Vec<f64> {
+    audio.iter().map(|x| -x).collect()
+}
+
+pub fn ramp(frames: std::ops::Range<usize>) -> Vec<f64> {
+    frames.map(|i| i as f64 / 32.0).collect()
+}
@@ -0,0 +1,54 @@
+//! Fixtures shared by the integration tests.
+
+// each test crate only uses some of these
+#![allow(dead_code)]
+
+pub mod edits;
+
+use condemus::*;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub const RATE: f64 = 8000.0;
+
+/// A project with nothing but a mono sample of the given audio, at [`RATE`].
+pub fn project(audio: Vec<f64>) -> Arc<Project> {
+    sample_project(Sample::mono(audio, RATE))
+}
+
+/// A project with nothing but the given sample.
+pub fn sample_project(sample: Sample) -> Arc<Project> {
+    Arc::new(Project {
+        patterns: vec![],
+        samples: vec![sample],
+        instruments: vec![],
+        tracks: vec![],
+    })
+}
+
+/// A sine wave of a frequency, at [`RATE`].
+pub fn sine(freq: f64, len: usize) -> Vec<f64> {
+    (0..len)
+        .map(|i| (i as f64 * freq * std::f64::consts::TAU / RATE).sin())
+        .collect()
+}
+
+/// Audio whose frames read the time they are at, in seconds, at [`RATE`].
+pub fn time_ramp(len: usize) -> Vec<f64> {
+    (0..len).map(|i| i as f64 / RATE).collect()
+}
+
+/// A path in the temporary directory, removed again when dropped.
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+    pub fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("condemus-{}-{}", std::process::id(), name)))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
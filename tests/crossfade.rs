@@ -0,0 +1,83 @@
+mod common;
+
+use common::edits::*;
+use common::*;
+use condemus::*;
+
+#[test]
+fn baked_crossfade_blends_the_end_of_the_loop_into_what_leads_to_its_start() {
+    let section = LoopSection {
+        crossfade: 0.4,
+        ..LoopSection::new(0.8, 1.4)
+    };
+
+    // frames 10 to 13 fade into frames 4 to 7
+    let mut expected = ramp(0..16);
+    for i in 0..4 {
+        let progress = i as f64 / 4.0;
+        expected[10 + i] = expected[10 + i] * (1.0 - progress) + expected[4 + i] * progress;
+    }
+
+    check(
+        |sample| {
+            let (baked, baked_section) = sample.bake_loop_crossfade(section);
+            assert_eq!(baked_section.crossfade, 0.0);
+            assert_eq!((baked_section.from, baked_section.to), (0.8, 1.4));
+            baked
+        },
+        &expected,
+    );
+
+    // the crossfade is shortened to the audio there is before the loop
+    let early = LoopSection {
+        crossfade: 0.5,
+        ..LoopSection::new(0.2, 0.6)
+    };
+    let mut expected = ramp(0..16);
+    for i in 0..2 {
+        let progress = i as f64 / 2.0;
+        expected[4 + i] = expected[4 + i] * (1.0 - progress) + expected[i] * progress;
+    }
+
+    check(|sample| sample.bake_loop_crossfade(early).0, &expected);
+}
+
+/// Plays a sample through a forward loop, at its own rate.
+fn play_loop(sample: Sample, section: LoopSection) -> Vec<f64> {
+    let mut sampler = BasicSamplerState::new(
+        sample_project(sample),
+        0,
+        BasicMode {
+            start: 0.0,
+            loops: vec![LoopDef::Forward(section)],
+        },
+        NoteStart::default(),
+    );
+
+    let mut left = vec![0.0; 4000];
+    let mut right = vec![0.0; 4000];
+    sampler.render(
+        AudioBufferSlice::new(&mut left, RATE, &LinearResampler),
+        AudioBufferSlice::new(&mut right, RATE, &LinearResampler),
+        1.0,
+    );
+
+    left
+}
+
+#[test]
+fn baked_crossfade_plays_like_the_crossfaded_loop() {
+    let sample = Sample::mono(sine(310.0, 4000), RATE);
+    let section = LoopSection {
+        crossfade: 0.05,
+        ..LoopSection::new(0.1, 0.2)
+    };
+    let (baked, baked_section) = sample.bake_loop_crossfade(section);
+
+    let faded = play_loop(sample, section);
+    let baked = play_loop(baked, baked_section);
+
+    for (i, (a, b)) in faded.iter().zip(&baked).enumerate() {
+        assert!((a - b).abs() < 1e-6, "differ at frame {}: {} {}", i, a, b);
+    }
+}
@@ -0,0 +1,999 @@
+mod common;
+
+use common::*;
+use condemus::*;
+use std::sync::Arc;
+
+fn instrument(volume_envelope: Option<Envelope>, pitch_envelope: Option<Envelope>) -> Instrument {
+    Instrument {
+        sample: 0,
+        volume: 1.0,
+        pan: 0.0,
+        base_pitch: 60.0,
+        mode: InstrumentMode::Basic(BasicMode {
+            start: 0.0,
+            loops: vec![LoopDef::Forward(LoopSection::new(0.1, 0.2))],
+        }),
+        zones: vec![],
+        volume_envelope,
+        pitch_envelope,
+        pan_envelope: None,
+    }
+}
+
+fn note(instrument: usize, pitch: f64, effects: Vec<(f64, Effect)>) -> Instruction {
+    Instruction::Note(NoteInstruction {
+        instrument,
+        pitch,
+        pan: 0.0,
+        volume: 0.5,
+        effects: effects
+            .into_iter()
+            .map(|(length, effect)| EffectInstance { length, effect })
+            .collect(),
+        start: NoteStart::default(),
+    })
+}
+
+fn vibration(speed: f64, depth: f64, waveform: LfoWaveform) -> Vibration {
+    Vibration {
+        speed,
+        depth,
+        waveform,
+        phase: 0.0,
+        fade_in: 0.0,
+    }
+}
+
+/// A project with a track that plays each pattern after the other, and a sine sample.
+fn song_project(patterns: Vec<Pattern>, instruments: Vec<Instrument>) -> Arc<Project> {
+    let pattern_refs = (0..patterns.len())
+        .map(|pattern| PatternRef {
+            position: pattern as f64 * 0.75,
+            pattern,
+        })
+        .collect();
+
+    Arc::new(Project {
+        patterns,
+        samples: vec![Sample::mono(sine(220.0, 2400), RATE)],
+        instruments,
+        tracks: vec![Track {
+            pattern_refs,
+            metadata: TrackMetadata {
+                name: "song".into(),
+                init_tempo: 120.0,
+                init_volume: 1.0,
+            },
+        }],
+    })
+}
+
+/// A song that uses every kind of channel effect, on top of envelopes and glides.
+fn song() -> Arc<Project> {
+    let lead = vec![
+        note(
+            0,
+            60.0,
+            vec![
+                (0.6, Effect::Vibrato(vibration(5.0, 0.5, LfoWaveform::Sine))),
+                (
+                    0.4,
+                    Effect::Tremolo(vibration(7.0, 0.3, LfoWaveform::Triangle)),
+                ),
+            ],
+        ),
+        Instruction::None,
+        note(
+            0,
+            67.0,
+            vec![
+                (0.3, Effect::TonePortamento(0.3)),
+                (
+                    0.5,
+                    Effect::Panbrello(Vibration {
+                        phase: 0.25,
+                        fade_in: 0.2,
+                        ..vibration(3.0, 0.8, LfoWaveform::SampleAndHold { seed: 7 })
+                    }),
+                ),
+            ],
+        ),
+        Instruction::None,
+        note(
+            0,
+            62.0,
+            vec![(
+                0.4,
+                Effect::Portamento(Slide {
+                    length: 0.3,
+                    amount: -5.0,
+                }),
+            )],
+        ),
+        Instruction::Fade(0.2),
+    ];
+
+    let drums = vec![
+        note(
+            1,
+            60.0,
+            vec![(
+                0.5,
+                Effect::Retrigger(Retrigger {
+                    interval: Interval::Rows(0.33),
+                    volume: Some(0.8),
+                }),
+            )],
+        ),
+        note(1, 72.0, vec![(0.1, Effect::NoteDelay(0.37))]),
+        note(
+            1,
+            60.0,
+            vec![
+                (0.01, Effect::NoteCut(0.07)),
+                (
+                    0.1,
+                    Effect::Vibrato(vibration(20.0, 2.0, LfoWaveform::Square)),
+                ),
+            ],
+        ),
+        note(
+            1,
+            55.0,
+            vec![(
+                0.5,
+                Effect::Arpeggio(Arpeggio {
+                    offsets: vec![0.0, 3.0, 7.0, 11.5],
+                    rate: 17.0,
+                    order: ArpeggioOrder::Random,
+                    seed: 3,
+                }),
+            )],
+        ),
+        Instruction::Stop,
+        note(
+            1,
+            48.0,
+            vec![(
+                0.3,
+                Effect::Tremolo(vibration(11.0, 0.4, LfoWaveform::SawDown)),
+            )],
+        ),
+    ];
+
+    let instructions = lead
+        .into_iter()
+        .zip(drums)
+        .flat_map(|(lead, drums)| [lead, drums])
+        .collect();
+
+    let pattern = Pattern {
+        instructions,
+        width: 2,
+        height: 6,
+        commands: vec![],
+        row_speed: 7.0,
+    };
+
+    song_project(
+        vec![pattern.clone(), pattern],
+        vec![
+            instrument(None, Some(Envelope::adsr(0.05, 0.1, -1.0, 0.2))),
+            instrument(Some(Envelope::adsr(0.01, 0.05, 0.6, 0.1)), None),
+        ],
+    )
+}
+
+fn render(data: Arc<Project>, len: usize, block_len: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut state = RenderState::new(data);
+    state.set_track(0);
+
+    let mut left = vec![0.0; len];
+    let mut right = vec![0.0; len];
+
+    for (l, r) in left.chunks_mut(block_len).zip(right.chunks_mut(block_len)) {
+        state.render(
+            AudioBufferSlice::new(l, RATE, &LinearResampler),
+            AudioBufferSlice::new(r, RATE, &LinearResampler),
+        );
+    }
+
+    (left, right)
+}
+
+#[test]
+fn output_does_not_depend_on_block_size() {
+    let len = RATE as usize * 2;
+    let (left, right) = render(song(), len, len);
+
+    assert!(left.iter().any(|x| x.abs() > 0.1), "output is silent");
+
+    for block_len in [1, 37, 64, 100, 512] {
+        let (split_left, split_right) = render(song(), len, block_len);
+
+        for (i, (a, b)) in left
+            .iter()
+            .zip(&right)
+            .zip(split_left.iter().zip(&split_right))
+            .enumerate()
+        {
+            assert!(
+                (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9,
+                "blocks of {} differ at frame {}",
+                block_len,
+                i
+            );
+        }
+    }
+}
+
+#[test]
+fn vibrations_leave_no_offset_behind() {
+    let pattern = |effects| Pattern {
+        instructions: vec![note(0, 60.0, effects), Instruction::None],
+        width: 1,
+        height: 2,
+        commands: vec![],
+        row_speed: 1.0,
+    };
+    let instruments = vec![instrument(None, None)];
+
+    let plain = song_project(vec![pattern(vec![])], instruments.clone());
+    let trembling = song_project(
+        vec![pattern(vec![(
+            0.5,
+            Effect::Tremolo(vibration(3.3, 0.4, LfoWaveform::Sine)),
+        )])],
+        instruments,
+    );
+
+    let (plain, _) = render(plain, RATE as usize, 100);
+    let (trembling, _) = render(trembling, RATE as usize, 100);
+
+    // once the tremolo is over, the volume is back where it started
+    let after = (0.5 * RATE) as usize + channels::CONTROL_FRAMES;
+    assert!(trembling[..after].iter().zip(&plain).any(|(a, b)| a != b));
+
+    for (a, b) in trembling[after..].iter().zip(&plain[after..]) {
+        assert!((a - b).abs() < 1e-12);
+    }
+}
+
+/// Frequency of the output between two frames, from its rising zero crossings.
+fn frequency(out: &[f64], from: usize, to: usize) -> f64 {
+    let crossings = out[from..to]
+        .windows(2)
+        .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+        .count();
+
+    crossings as f64 * RATE / (to - from) as f64
+}
+
+#[test]
+fn slides_cut_short_keep_the_pitch_they_got_to() {
+    let slide = Effect::Portamento(Slide {
+        length: 1.0,
+        amount: 12.0,
+    });
+    let data = song_project(
+        vec![Pattern {
+            instructions: vec![note(0, 60.0, vec![(0.5, slide)]), Instruction::None],
+            width: 1,
+            height: 2,
+            commands: vec![],
+            row_speed: 1.0,
+        }],
+        vec![instrument(None, None)],
+    );
+
+    let (out, _) = render(data, RATE as usize * 2, 100);
+
+    // the effect ends halfway through the slide, so the pitch stays half an octave up
+    let expected = 220.0 * 2.0_f64.powf(0.5);
+    let measured = frequency(&out, (0.6 * RATE) as usize, (1.6 * RATE) as usize);
+    assert!(
+        (measured - expected).abs() < 3.0,
+        "expected {} Hz, got {} Hz",
+        expected,
+        measured
+    );
+}
+
+#[test]
+fn stereo_samples_play_as_loud_as_mono_ones() {
+    let pattern = Pattern {
+        instructions: vec![note(0, 60.0, vec![]), Instruction::None],
+        width: 1,
+        height: 2,
+        commands: vec![],
+        row_speed: 1.0,
+    };
+    let mono = song_project(vec![pattern.clone()], vec![instrument(None, None)]);
+
+    // the same audio on both channels
+    let mut stereo = song_project(vec![pattern], vec![instrument(None, None)]);
+    let audio: Vec<f64> = sine(220.0, 2400).iter().flat_map(|x| [*x, *x]).collect();
+    Arc::get_mut(&mut stereo).unwrap().samples[0] = Sample {
+        audio: audio.into(),
+        baserate: RATE,
+        channels: 2,
+        layout: ChannelLayout::Interleaved,
+    };
+
+    let mono = render(mono, RATE as usize, 100);
+    let stereo = render(stereo, RATE as usize, 100);
+
+    assert!(mono.0.iter().any(|x| x.abs() > 0.1), "output is silent");
+
+    for (a, b) in mono
+        .0
+        .iter()
+        .zip(&stereo.0)
+        .chain(mono.1.iter().zip(&stereo.1))
+    {
+        assert!((a - b).abs() < 1e-12);
+    }
+}
+
+#[test]
+fn retriggers_after_a_stop_stay_released() {
+    let retrigger = Effect::Retrigger(Retrigger {
+        interval: Interval::Seconds(0.5),
+        volume: None,
+    });
+    let mut instructions = vec![note(0, 60.0, vec![(1.5, retrigger)]), Instruction::Stop];
+    instructions.resize(5, Instruction::None);
+
+    let mut data = song_project(
+        vec![Pattern {
+            instructions,
+            width: 1,
+            height: 5,
+            commands: vec![],
+            row_speed: 1.0,
+        }],
+        vec![Instrument {
+            mode: InstrumentMode::Basic(BasicMode {
+                start: 0.0,
+                loops: vec![LoopDef::Forward(LoopSection::new(0.2, 0.4))],
+            }),
+            ..instrument(None, None)
+        }],
+    );
+
+    // a ramp a second long, which would be heard again if the note went back into its loop
+    let ramp: Vec<f64> = (0..RATE as usize).map(|i| 0.1 + i as f64 / RATE).collect();
+    Arc::get_mut(&mut data).unwrap().samples[0] = Sample::mono(ramp, RATE);
+
+    let (out, _) = render(data, RATE as usize * 5, 100);
+
+    // the last retrigger is at 1.5 s, and plays the whole sample through once
+    assert!(out[(1.6 * RATE) as usize..(2.4 * RATE) as usize]
+        .iter()
+        .any(|x| x.abs() > 0.1));
+    assert!(out[(2.6 * RATE) as usize..].iter().all(|x| *x == 0.0));
+}
+
+/// A project that plays a note without loops on the first of a few rows, over a ramp a second
+/// long that reads [`ramp_at`].
+fn ramp_note(effects: Vec<(f64, Effect)>, row_speed: f64) -> Arc<Project> {
+    let mut instructions = vec![note(0, 60.0, effects)];
+    instructions.resize(4, Instruction::None);
+
+    let mut data = song_project(
+        vec![Pattern {
+            instructions,
+            width: 1,
+            height: 4,
+            commands: vec![],
+            row_speed,
+        }],
+        vec![Instrument {
+            mode: InstrumentMode::Basic(BasicMode {
+                start: 0.0,
+                loops: vec![],
+            }),
+            ..instrument(None, None)
+        }],
+    );
+
+    let ramp: Vec<f64> = (0..RATE as usize).map(ramp_at).collect();
+    Arc::get_mut(&mut data).unwrap().samples[0] = Sample::mono(ramp, RATE);
+    data
+}
+
+fn ramp_at(frame: usize) -> f64 {
+    0.1 + 0.5 * frame as f64 / RATE
+}
+
+/// Checks that the output of a centered note at half volume plays a frame of the ramp.
+fn assert_plays(out: &[f64], frame: usize, ramp_frame: usize, volume: f64) {
+    let expected = ramp_at(ramp_frame) * volume * 0.25;
+    assert!(
+        (out[frame] - expected).abs() < 1e-9,
+        "expected {} at frame {}, got {}",
+        expected,
+        frame,
+        out[frame]
+    );
+}
+
+#[test]
+fn delayed_note_starts_on_its_frame() {
+    let data = ramp_note(vec![(0.5, Effect::NoteDelay(0.25))], 1.0);
+    let (out, _) = render(data, RATE as usize * 2, 100);
+
+    // a quarter of a row at a row per second
+    assert!(out[..2000].iter().all(|x| *x == 0.0));
+    assert_plays(&out, 2000, 0, 1.0);
+    assert_plays(&out, 3000, 1000, 1.0);
+}
+
+#[test]
+fn cut_note_goes_silent_on_its_frame() {
+    // the cut outlasts the effect
+    let data = ramp_note(vec![(0.01, Effect::NoteCut(0.1))], 1.0);
+    let (out, _) = render(data, RATE as usize, 100);
+
+    assert_plays(&out, 799, 799, 1.0);
+    assert!(out[800..].iter().all(|x| *x == 0.0));
+}
+
+#[test]
+fn retrigger_restarts_the_sample_every_interval() {
+    let retrigger = |interval, volume| Effect::Retrigger(Retrigger { interval, volume });
+
+    // a quarter of a second, in seconds and in rows
+    for (interval, row_speed) in [(Interval::Seconds(0.25), 1.0), (Interval::Rows(0.5), 2.0)] {
+        let data = ramp_note(vec![(0.6, retrigger(interval, None))], row_speed);
+        let (out, _) = render(data, RATE as usize * 2, 100);
+
+        assert_plays(&out, 1999, 1999, 1.0);
+        assert_plays(&out, 2000, 0, 1.0);
+        assert_plays(&out, 3999, 1999, 1.0);
+        assert_plays(&out, 4000, 0, 1.0);
+
+        // the effect is over before a third retrigger
+        assert_plays(&out, 6000, 2000, 1.0);
+    }
+
+    // each retrigger scales the volume
+    let data = ramp_note(
+        vec![(0.6, retrigger(Interval::Seconds(0.25), Some(0.5)))],
+        1.0,
+    );
+    let (out, _) = render(data, RATE as usize, 100);
+
+    assert_plays(&out, 1999, 1999, 1.0);
+    assert_plays(&out, 2000, 0, 0.5);
+    assert_plays(&out, 4000, 0, 0.25);
+}
+
+/// A project that plays a looping sine at 220 Hz for a few seconds, with the given effects.
+fn sine_note(effects: Vec<(f64, Effect)>) -> Arc<Project> {
+    let mut instructions = vec![note(0, 60.0, effects)];
+    instructions.resize(4, Instruction::None);
+
+    song_project(
+        vec![Pattern {
+            instructions,
+            width: 1,
+            height: 4,
+            commands: vec![],
+            row_speed: 1.0,
+        }],
+        vec![instrument(None, None)],
+    )
+}
+
+fn arpeggio(offsets: Vec<f64>, rate: f64, order: ArpeggioOrder, seed: u64) -> Effect {
+    Effect::Arpeggio(Arpeggio {
+        offsets,
+        rate,
+        order,
+        seed,
+    })
+}
+
+/// The frequency the output plays at in each step of an arpeggio, measured away from the edges
+/// of the step.
+fn arpeggio_steps(out: &[f64], rate: f64, steps: usize) -> Vec<f64> {
+    let step_len = RATE / rate;
+
+    (0..steps)
+        .map(|step| {
+            let from = ((step as f64 + 0.1) * step_len) as usize;
+            let to = ((step as f64 + 0.9) * step_len) as usize;
+            frequency(out, from, to)
+        })
+        .collect()
+}
+
+fn assert_frequencies(measured: &[f64], semitones: &[f64]) {
+    assert_eq!(measured.len(), semitones.len());
+
+    for (step, (measured, semitones)) in measured.iter().zip(semitones).enumerate() {
+        let expected = 220.0 * 2.0_f64.powf(semitones / 12.0);
+        assert!(
+            (measured - expected).abs() < 4.0,
+            "expected {} Hz on step {}, got {} Hz",
+            expected,
+            step,
+            measured
+        );
+    }
+}
+
+#[test]
+fn arpeggio_steps_through_its_offsets_in_order() {
+    let offsets = vec![0.0, 7.0, -12.0];
+
+    let data = sine_note(vec![(
+        3.0,
+        arpeggio(offsets.clone(), 2.0, ArpeggioOrder::Up, 0),
+    )]);
+    let (out, _) = render(data, RATE as usize * 3, 100);
+    assert_frequencies(&arpeggio_steps(&out, 2.0, 5), &[0.0, 7.0, -12.0, 0.0, 7.0]);
+
+    let data = sine_note(vec![(3.0, arpeggio(offsets, 2.0, ArpeggioOrder::Down, 0))]);
+    let (out, _) = render(data, RATE as usize * 3, 100);
+    assert_frequencies(
+        &arpeggio_steps(&out, 2.0, 5),
+        &[-12.0, 7.0, 0.0, -12.0, 7.0],
+    );
+}
+
+#[test]
+fn random_arpeggio_is_the_same_for_a_seed() {
+    let play = |seed| {
+        let data = sine_note(vec![(
+            3.0,
+            arpeggio(vec![0.0, 12.0], 4.0, ArpeggioOrder::Random, seed),
+        )]);
+        render(data, RATE as usize * 3, 100).0
+    };
+
+    let out = play(11);
+    assert_eq!(out, play(11));
+    assert_ne!(out, play(12));
+
+    // every step plays one of the offsets, and both come up
+    let steps = arpeggio_steps(&out, 4.0, 12);
+    let octave_up = steps.iter().filter(|frequency| **frequency > 330.0).count();
+
+    for frequency in &steps {
+        assert!(
+            (frequency - 220.0).abs() < 6.0 || (frequency - 440.0).abs() < 6.0,
+            "got {} Hz",
+            frequency
+        );
+    }
+    assert!(octave_up > 0 && octave_up < steps.len());
+}
+
+#[test]
+fn arpeggio_leaves_the_pitch_alone_once_over() {
+    let data = sine_note(vec![(
+        1.0,
+        arpeggio(vec![5.0, 9.0], 2.0, ArpeggioOrder::Up, 0),
+    )]);
+    let (out, _) = render(data, RATE as usize * 2, 100);
+
+    assert_frequencies(&arpeggio_steps(&out, 2.0, 4), &[5.0, 9.0, 0.0, 0.0]);
+}
+
+fn assert_waveform(waveform: LfoWaveform, expected: &[(f64, f64)]) {
+    for (cycles, value) in expected {
+        assert!(
+            (waveform.value(*cycles) - value).abs() < 1e-9,
+            "{:?} at {} cycles: expected {}, got {}",
+            waveform,
+            cycles,
+            value,
+            waveform.value(*cycles)
+        );
+    }
+}
+
+#[test]
+fn waveforms_hit_their_values_at_known_phases() {
+    use LfoWaveform::*;
+
+    assert_waveform(Sine, &[(0.0, 0.0), (0.25, 1.0), (0.5, 0.0), (0.75, -1.0)]);
+    assert_waveform(
+        Triangle,
+        &[
+            (0.0, 0.0),
+            (0.125, 0.5),
+            (0.25, 1.0),
+            (0.5, 0.0),
+            (0.75, -1.0),
+            (1.0, 0.0),
+        ],
+    );
+    assert_waveform(
+        Square,
+        &[(0.0, 1.0), (0.49, 1.0), (0.5, -1.0), (0.99, -1.0)],
+    );
+    assert_waveform(
+        SawUp,
+        &[
+            (0.0, 0.0),
+            (0.25, 0.5),
+            (0.5, -1.0),
+            (0.75, -0.5),
+            (1.25, 0.5),
+        ],
+    );
+    assert_waveform(
+        SawDown,
+        &[
+            (0.0, 0.0),
+            (0.25, -0.5),
+            (0.5, 1.0),
+            (0.75, 0.5),
+            (-0.75, -0.5),
+        ],
+    );
+}
+
+#[test]
+fn sample_and_hold_holds_a_value_for_each_cycle() {
+    let waveform = LfoWaveform::SampleAndHold { seed: 5 };
+    let cycles: Vec<f64> = (0..20).map(|cycle| waveform.value(cycle as f64)).collect();
+
+    for (cycle, value) in cycles.iter().enumerate() {
+        assert!((-1.0..=1.0).contains(value));
+        assert_eq!(waveform.value(cycle as f64 + 0.7), *value);
+    }
+    assert!(cycles.windows(2).any(|pair| pair[0] != pair[1]));
+
+    let other = LfoWaveform::SampleAndHold { seed: 6 };
+    assert!((0..20).any(|cycle| other.value(cycle as f64) != cycles[cycle]));
+}
+
+#[test]
+fn vibration_phase_and_fade_in_shape_the_offset() {
+    let triangle = Vibration {
+        phase: 0.25,
+        ..vibration(2.0, 0.5, LfoWaveform::Triangle)
+    };
+
+    // a quarter cycle in, the triangle starts at its top
+    assert!((triangle.offset_at(0.0) - 0.5).abs() < 1e-9);
+    assert!((triangle.offset_at(0.25) + 0.5).abs() < 1e-9);
+
+    let fading = Vibration {
+        fade_in: 1.0,
+        ..triangle
+    };
+    assert_eq!(fading.offset_at(0.0), 0.0);
+    assert!((fading.offset_at(0.5) - 0.25).abs() < 1e-9);
+    assert!((fading.offset_at(1.5) - 0.5).abs() < 1e-9);
+}
+
+#[test]
+fn square_tremolo_switches_the_volume() {
+    let tremolo = Effect::Tremolo(vibration(2.0, 0.25, LfoWaveform::Square));
+    let data = ramp_note(vec![(1.0, tremolo)], 1.0);
+    let (out, _) = render(data, RATE as usize, 100);
+
+    // half a cycle up, then half a cycle down, from a note at half volume, held for control
+    // periods
+    let control = channels::CONTROL_FRAMES;
+    let down = 2000_usize.next_multiple_of(control);
+    let up = 4000_usize.next_multiple_of(control);
+
+    assert_plays(&out, 100, 100, 1.5);
+    assert_plays(&out, down - 1, down - 1, 1.5);
+    assert_plays(&out, down, down, 0.5);
+    assert_plays(&out, up - 1, up - 1, 0.5);
+    assert_plays(&out, up, up, 1.5);
+}
+
+/// Renders a second of a note played by an instrument with zones, over samples a second long
+/// where sample `k` reads `k` plus the time it is at.
+fn zoned_note(pitch: f64, volume: f64) -> Vec<f64> {
+    let Instruction::Note(ins) = note(0, pitch, vec![]) else {
+        unreachable!()
+    };
+    let unlooped = InstrumentMode::Basic(BasicMode {
+        start: 0.0,
+        loops: vec![],
+    });
+    let range = |from, to| ValueRange { from, to };
+
+    let mut data = song_project(
+        vec![Pattern {
+            instructions: vec![
+                Instruction::Note(NoteInstruction { volume, ..ins }),
+                Instruction::None,
+            ],
+            width: 1,
+            height: 2,
+            commands: vec![],
+            row_speed: 1.0,
+        }],
+        vec![Instrument {
+            mode: unlooped.clone(),
+            zones: vec![
+                Zone {
+                    pitch: range(48.0, 59.0),
+                    volume: None,
+                    sample: 1,
+                    base_pitch: 48.0,
+                    mode: unlooped.clone(),
+                },
+                Zone {
+                    pitch: range(60.0, 71.0),
+                    volume: Some(range(0.0, 0.5)),
+                    sample: 2,
+                    base_pitch: 72.0,
+                    mode: InstrumentMode::Basic(BasicMode {
+                        start: 0.0,
+                        loops: vec![LoopDef::Forward(LoopSection::new(0.1, 0.2))],
+                    }),
+                },
+                // covers every pitch, behind the other zones
+                Zone {
+                    pitch: range(0.0, 127.0),
+                    volume: Some(range(0.9, 1.0)),
+                    sample: 3,
+                    base_pitch: 60.0,
+                    mode: unlooped,
+                },
+            ],
+            ..instrument(None, None)
+        }],
+    );
+
+    Arc::get_mut(&mut data).unwrap().samples = (0..4)
+        .map(|k| {
+            let ramp = (0..RATE as usize).map(|i| k as f64 + i as f64 / RATE);
+            Sample::mono(ramp.collect::<Vec<_>>(), RATE)
+        })
+        .collect();
+
+    render(data, RATE as usize, 100).0
+}
+
+/// Checks that a centered note plays a value at a frame.
+fn assert_value(out: &[f64], frame: usize, value: f64, volume: f64) {
+    let expected = value * volume * 0.5;
+    assert!(
+        (out[frame] - expected).abs() < 1e-9,
+        "expected {} at frame {}, got {}",
+        expected,
+        frame,
+        out[frame]
+    );
+}
+
+#[test]
+fn notes_play_the_zone_they_fall_in() {
+    let half = RATE as usize / 2;
+
+    // the zone's own sample at its base pitch
+    assert_value(&zoned_note(48.0, 0.5), half, 1.5, 0.5);
+
+    // the zone's loop, at half speed an octave below its base pitch
+    assert_value(&zoned_note(60.0, 0.5), half, 2.15, 0.5);
+
+    // too loud for the zone of its pitch, so the one behind it
+    assert_value(&zoned_note(60.0, 1.0), half, 3.5, 1.0);
+}
+
+#[test]
+fn first_matching_zone_wins() {
+    // the zone behind also covers the note, and would play sample 3 at full speed
+    let semitones_up = 2.0_f64.powf(2.0 / 12.0);
+    assert_value(
+        &zoned_note(50.0, 1.0),
+        RATE as usize / 2,
+        1.0 + 0.5 * semitones_up,
+        1.0,
+    );
+}
+
+#[test]
+fn notes_outside_every_zone_play_the_instrument_sample() {
+    let out = zoned_note(90.0, 0.5);
+
+    // sample 0 without loops, two and a half octaves above the instrument's base pitch
+    let rate = 2.0_f64.powf(30.0 / 12.0);
+    assert_value(&out, 400, 400.0 / RATE * rate, 0.5);
+    assert!(out[(1.0 / rate * RATE) as usize + 1..]
+        .iter()
+        .all(|x| *x == 0.0));
+}
+
+/// Renders two notes half a second apart, the second an octave up with a tone portamento, over a
+/// ramp four seconds long that rises by 0.1 each second.
+fn glided_notes(second_instrument: usize) -> Vec<f64> {
+    let glide = Effect::TonePortamento(0.25);
+    let unlooped = Instrument {
+        mode: InstrumentMode::Basic(BasicMode {
+            start: 0.0,
+            loops: vec![],
+        }),
+        ..instrument(None, None)
+    };
+
+    let mut data = song_project(
+        vec![Pattern {
+            instructions: vec![
+                note(0, 60.0, vec![]),
+                note(second_instrument, 72.0, vec![(1.0, glide)]),
+                Instruction::None,
+                Instruction::None,
+            ],
+            width: 1,
+            height: 4,
+            commands: vec![],
+            row_speed: 2.0,
+        }],
+        vec![unlooped.clone(), unlooped],
+    );
+
+    let ramp: Vec<f64> = (0..RATE as usize * 4)
+        .map(|i| 0.1 * i as f64 / RATE)
+        .collect();
+    Arc::get_mut(&mut data).unwrap().samples[0] = Sample::mono(ramp, RATE);
+
+    render(data, RATE as usize * 2, 100).0
+}
+
+/// How fast the sample plays between two times, from how fast the ramp rises.
+fn ramp_rate(out: &[f64], from: f64, to: f64) -> f64 {
+    let frame = |secs: f64| (secs * RATE) as usize;
+    // the ramp rises by 0.1 each second, played by a centered note at half volume
+    (out[frame(to)] - out[frame(from)]) / (to - from) / 0.1 / 0.25
+}
+
+#[test]
+fn tone_portamento_glides_without_restarting_the_sample() {
+    let out = glided_notes(0);
+    let second = (0.5 * RATE) as usize;
+
+    // the sample carries on from where the first note got to
+    assert!((out[second] - 0.05 * 0.25).abs() < 1e-4);
+    assert!((ramp_rate(&out, 0.4, 0.5) - 1.0).abs() < 1e-9);
+
+    // on its way up, then an octave up from the first control period after the glide
+    let gliding = ramp_rate(&out, 0.55, 0.7);
+    assert!(gliding > 1.1 && gliding < 1.9, "{}", gliding);
+    assert!((ramp_rate(&out, 0.76, 1.5) - 2.0).abs() < 1e-9);
+}
+
+#[test]
+fn tone_portamento_to_another_instrument_retriggers() {
+    let out = glided_notes(1);
+    let second = (0.5 * RATE) as usize;
+
+    // the sample starts over, right away at the pitch of the new note
+    assert!(out[second].abs() < 1e-4);
+    assert!((ramp_rate(&out, 0.55, 0.7) - 2.0).abs() < 1e-9);
+}
+
+/// Plays a pattern of a row a second, with an instrument that holds a sustain loop over the ramp
+/// that reads [`ramp_at`], returning the output and the state the pattern was left in.
+fn play_sustained(instructions: Vec<Instruction>, secs: f64) -> (Vec<f64>, PatternState) {
+    let height = instructions.len() as u16;
+    let mut data = song_project(
+        vec![Pattern {
+            instructions,
+            width: 1,
+            height,
+            commands: vec![],
+            row_speed: 1.0,
+        }],
+        vec![instrument(None, None)],
+    );
+
+    let ramp: Vec<f64> = (0..RATE as usize).map(ramp_at).collect();
+    Arc::get_mut(&mut data).unwrap().samples[0] = Sample::mono(ramp, RATE);
+
+    let mut state = PatternState::new(data, 0);
+    let mut left = vec![0.0; (secs * RATE) as usize];
+    let mut right = vec![0.0; left.len()];
+
+    for (l, r) in left.chunks_mut(100).zip(right.chunks_mut(100)) {
+        state.render(
+            AudioBufferSlice::new(l, RATE, &LinearResampler),
+            AudioBufferSlice::new(r, RATE, &LinearResampler),
+        );
+    }
+
+    (left, state)
+}
+
+#[test]
+fn held_note_stays_in_its_sustain_loop() {
+    let (out, mut state) = play_sustained(vec![note(0, 60.0, vec![]), Instruction::None], 1.9);
+
+    // the loop plays 0.1 to 0.2 seconds into the ramp, over and over
+    let (low, high) = (ramp_at(800) * 0.25, ramp_at(1600) * 0.25);
+    assert!(out[1600..].iter().all(|x| *x >= low - 1e-9 && *x <= high));
+    assert!(state.channels()[0].is_some());
+}
+
+#[test]
+fn stop_leaves_the_sustain_loop_and_frees_the_channel() {
+    let instructions = vec![note(0, 60.0, vec![]), Instruction::Stop, Instruction::None];
+    let (out, mut state) = play_sustained(instructions.clone(), 1.5);
+
+    // at 1 second, playback is back at the start of the loop, and carries on past its end
+    let stop = RATE as usize;
+    assert_plays(&out, stop + 400, 1200, 1.0);
+    assert_plays(&out, stop + 1200, 2000, 1.0);
+    assert!(state.channels()[0].is_some());
+
+    // the rest of the ramp is over by 1.9 seconds, and the pattern lets go of the channel
+    let (out, mut state) = play_sustained(instructions, 2.5);
+    assert!(out[(1.9 * RATE) as usize + 1..].iter().all(|x| *x == 0.0));
+    assert!(state.channels()[0].is_none());
+}
+
+#[test]
+fn fade_reaches_silence_after_its_length() {
+    let instructions = vec![
+        note(0, 60.0, vec![]),
+        Instruction::Fade(0.5),
+        Instruction::None,
+    ];
+    let (out, mut state) = play_sustained(instructions, 2.5);
+
+    // halfway through, half as loud, 0.15 seconds into the ramp
+    assert_plays(&out, (1.25 * RATE) as usize, 1200, 0.5);
+    assert!(out[(1.5 * RATE) as usize..].iter().all(|x| *x == 0.0));
+    assert!(state.channels()[0].is_none());
+}
+
+#[test]
+fn pan_envelope_moves_the_note_frame_by_frame() {
+    let sweep = Envelope {
+        points: vec![
+            EnvelopePoint {
+                time: 0.0,
+                value: -1.0,
+            },
+            EnvelopePoint {
+                time: 1.0,
+                value: 1.0,
+            },
+        ],
+        sustain: None,
+        looping: None,
+    };
+    let mut data = song_project(
+        vec![Pattern {
+            instructions: vec![note(0, 60.0, vec![]), Instruction::None],
+            width: 1,
+            height: 2,
+            commands: vec![],
+            row_speed: 1.0,
+        }],
+        vec![Instrument {
+            pan_envelope: Some(sweep),
+            ..instrument(None, None)
+        }],
+    );
+    Arc::get_mut(&mut data).unwrap().samples[0] = Sample::mono(vec![0.8; RATE as usize], RATE);
+
+    let (left, right) = render(data, RATE as usize, 100);
+
+    // from hard left to hard right over a second, on every frame rather than every control
+    // period, of a mono note at half volume
+    for frame in [0, 100, 101, 127, 2000, 4000, 7999] {
+        let panning = -1.0 + 2.0 * frame as f64 / RATE;
+        let expected = (0.4 * (1.0 - panning) / 2.0, 0.4 * (1.0 + panning) / 2.0);
+        assert!(
+            (left[frame] - expected.0).abs() < 1e-9 && (right[frame] - expected.1).abs() < 1e-9,
+            "expected {:?} at frame {}, got {:?}",
+            expected,
+            frame,
+            (left[frame], right[frame])
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use condemus::*;
+
+fn point(time: f64, value: f64) -> EnvelopePoint {
+    EnvelopePoint { time, value }
+}
+
+/// Advances a state through an envelope in steps of a hundredth of a second, releasing it at a
+/// step if asked, and returns its value after each of them.
+fn values(env: &Envelope, steps: usize, release_at: Option<usize>) -> Vec<f64> {
+    let mut state = EnvelopeState::new();
+
+    (0..steps)
+        .map(|step| {
+            if release_at == Some(step) {
+                state.release();
+            }
+
+            state.advance(env, 0.01);
+            state.value(env).unwrap()
+        })
+        .collect()
+}
+
+/// Checks the value after a step, which ends `(step + 1) / 100` seconds in.
+fn assert_value(values: &[f64], step: usize, expected: f64) {
+    assert!(
+        (values[step] - expected).abs() < 1e-9,
+        "expected {} after step {}, got {}",
+        expected,
+        step,
+        values[step]
+    );
+}
+
+#[test]
+fn held_note_holds_a_sustain_point() {
+    let env = Envelope::adsr(0.1, 0.1, 0.5, 0.2);
+    let held = values(&env, 100, None);
+
+    // up to 1 at 0.1 seconds, down to 0.5 at 0.2, then held there
+    assert_value(&held, 4, 0.5);
+    assert_value(&held, 9, 1.0);
+    assert_value(&held, 14, 0.75);
+    assert!(held[19..].iter().all(|x| (x - 0.5).abs() < 1e-9));
+}
+
+#[test]
+fn released_note_plays_on_past_the_sustain_point() {
+    let env = Envelope::adsr(0.1, 0.1, 0.5, 0.2);
+    let mut state = EnvelopeState::new();
+    state.advance(&env, 1.0);
+    assert!(!state.ended(&env));
+
+    // picks up from the sustain point, however long it was held
+    state.release();
+    state.advance(&env, 0.1);
+    assert!((state.value(&env).unwrap() - 0.25).abs() < 1e-9);
+    assert!(!state.ended(&env));
+
+    state.advance(&env, 0.15);
+    assert_eq!(state.value(&env), Some(0.0));
+    assert!(state.ended(&env));
+}
+
+#[test]
+fn sustain_loop_wraps_until_released() {
+    let env = Envelope {
+        points: vec![
+            point(0.0, 0.0),
+            point(0.1, 1.0),
+            point(0.3, 0.0),
+            point(0.5, 1.0),
+        ],
+        sustain: Some(EnvelopeLoop { from: 1, to: 2 }),
+        looping: None,
+    };
+    let out = values(&env, 60, Some(40));
+
+    // from 0.3 seconds, back to 0.1 and down again
+    assert_value(&out, 19, 0.5);
+    assert_value(&out, 31, 0.9);
+    assert_value(&out, 34, 0.75);
+    assert!(out[10..40].iter().all(|x| *x > 0.0));
+
+    // released halfway down the loop, it carries on down to 0.3 seconds, then rises past it
+    assert_value(&out, 49, 0.0);
+    assert_value(&out, 59, 0.5);
+}
+
+#[test]
+fn regular_loop_wraps_regardless_of_release() {
+    let env = Envelope {
+        points: vec![point(0.0, 0.0), point(0.2, 1.0), point(0.4, 0.0)],
+        sustain: None,
+        looping: Some(EnvelopeLoop { from: 0, to: 2 }),
+    };
+
+    for release_at in [None, Some(0)] {
+        let out = values(&env, 100, release_at);
+
+        assert_value(&out, 9, 0.5);
+        assert_value(&out, 49, 0.5);
+        assert_value(&out, 59, 1.0);
+        assert_value(&out, 74, 0.25);
+    }
+}
+
+#[test]
+fn gain_follows_the_envelope_frame_by_frame() {
+    let env = Envelope {
+        points: vec![point(0.0, 0.0), point(1.0, 1.0)],
+        sustain: None,
+        looping: None,
+    };
+    let mut left = vec![1.0; 12];
+    let mut right = vec![-0.5; 12];
+
+    EnvelopeState::new().apply_gain(&env, &mut left, &mut right, 10.0);
+
+    for i in 0..12 {
+        let gain = (i as f64 / 10.0).min(1.0);
+        assert!((left[i] - gain).abs() < 1e-9);
+        assert!((right[i] + gain * 0.5).abs() < 1e-9);
+    }
+}
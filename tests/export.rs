@@ -0,0 +1,189 @@
+mod common;
+
+use common::*;
+use condemus::*;
+use std::sync::Arc;
+
+/// A track that plays a note through a pattern, then the pattern again over the end of it.
+fn song() -> Arc<Project> {
+    let note = Instruction::Note(NoteInstruction {
+        instrument: 0,
+        pitch: 60.0,
+        pan: 0.0,
+        volume: 1.0,
+        effects: vec![],
+        start: NoteStart::default(),
+    });
+    let mut data = Arc::try_unwrap(project(sine(220.0, 8000))).ok().unwrap();
+
+    data.patterns.push(Pattern {
+        instructions: vec![note, Instruction::None, Instruction::None],
+        width: 1,
+        height: 3,
+        commands: vec![],
+        row_speed: 4.0,
+    });
+    data.instruments.push(Instrument {
+        sample: 0,
+        volume: 1.0,
+        pan: 0.0,
+        base_pitch: 60.0,
+        mode: InstrumentMode::Basic(BasicMode {
+            start: 0.0,
+            loops: vec![],
+        }),
+        zones: vec![],
+        volume_envelope: None,
+        pitch_envelope: None,
+        pan_envelope: None,
+    });
+    data.tracks.push(Track {
+        pattern_refs: vec![
+            PatternRef {
+                position: 0.1,
+                pattern: 0,
+            },
+            PatternRef {
+                position: 0.5,
+                pattern: 0,
+            },
+        ],
+        metadata: TrackMetadata {
+            name: "export".into(),
+            init_tempo: 120.0,
+            init_volume: 1.0,
+        },
+    });
+
+    Arc::new(data)
+}
+
+fn export(block_len: usize) -> Vec<f64> {
+    let options = RenderOptions {
+        rate: RATE as u32,
+        block_len,
+        ..RenderOptions::default()
+    };
+    let mut out = vec![];
+
+    render_track(song(), 0, &options, |left, _| {
+        out.extend_from_slice(left);
+        Ok(())
+    })
+    .unwrap();
+
+    out
+}
+
+#[test]
+fn rendered_track_ends_with_its_last_pattern() {
+    // the second pattern starts at 0.5 seconds and lasts 0.75
+    let len = (1.25 * RATE) as usize;
+    let out = export(1024);
+
+    assert_eq!(out.len(), len);
+    assert!(
+        out[len - 100..].iter().any(|x| x.abs() > 0.1),
+        "output ends early"
+    );
+
+    for block_len in [1, 100, 999, 4096, 20000] {
+        let split = export(block_len);
+        assert_eq!(
+            split.len(),
+            len,
+            "blocks of {} change the length",
+            block_len
+        );
+
+        for (i, (a, b)) in out.iter().zip(&split).enumerate() {
+            assert!(
+                (a - b).abs() < 1e-9,
+                "blocks of {} differ at frame {}",
+                block_len,
+                i
+            );
+        }
+    }
+}
+
+#[test]
+fn options_that_cannot_render_are_rejected() {
+    for options in [
+        RenderOptions {
+            rate: 0,
+            ..RenderOptions::default()
+        },
+        RenderOptions {
+            block_len: 0,
+            ..RenderOptions::default()
+        },
+    ] {
+        let rendered = render_track(song(), 0, &options, |_, _| Ok(()));
+        assert!(matches!(rendered, Err(RenderError::InvalidOptions(_))));
+
+        let mut wav = std::io::Cursor::new(vec![]);
+        let written = write_track_wav(song(), 0, &mut wav, &options);
+        assert!(matches!(written, Err(RenderError::InvalidOptions(_))));
+        assert!(wav.into_inner().is_empty());
+    }
+}
+
+#[test]
+fn missing_track_writes_nothing() {
+    let mut wav = std::io::Cursor::new(vec![]);
+    let written = write_track_wav(song(), 1, &mut wav, &RenderOptions::default());
+
+    assert!(matches!(written, Err(RenderError::NoSuchTrack(1))));
+    assert!(wav.into_inner().is_empty());
+
+    let file = TempFile::new("missing-track.wav");
+    let rendered = render_track_to_wav(song(), 1, &file.0, &RenderOptions::default());
+
+    assert!(matches!(rendered, Err(RenderError::NoSuchTrack(1))));
+    assert!(!file.0.exists());
+}
+
+#[test]
+fn pattern_that_never_moves_on_is_rejected() {
+    for row_speed in [0.0, -4.0, f64::NAN] {
+        let mut data = Arc::try_unwrap(song()).ok().unwrap();
+        data.patterns[0].row_speed = row_speed;
+
+        let rendered = render_track(Arc::new(data), 0, &RenderOptions::default(), |_, _| Ok(()));
+        assert!(matches!(rendered, Err(RenderError::InvalidRowSpeed(0))));
+    }
+}
+
+#[test]
+fn integer_wavs_round_to_the_nearest_step() {
+    let options = RenderOptions {
+        rate: RATE as u32,
+        ..RenderOptions::default()
+    };
+    let rendered = export(options.block_len);
+
+    for (format, scale) in [(WavFormat::Int16, 32768.0), (WavFormat::Int24, 8388608.0)] {
+        let mut wav = std::io::Cursor::new(vec![]);
+        write_track_wav(song(), 0, &mut wav, &RenderOptions { format, ..options }).unwrap();
+        wav.set_position(0);
+
+        let written: Vec<i32> = hound::WavReader::new(wav)
+            .unwrap()
+            .into_samples::<i32>()
+            .step_by(2)
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(written.len(), rendered.len());
+
+        for (i, (x, value)) in rendered.iter().zip(written).enumerate() {
+            let expected = (x * scale).round().clamp(-scale, scale - 1.0);
+            assert_eq!(
+                value as f64, expected,
+                "{:?} differs at frame {}",
+                format, i
+            );
+        }
+    }
+}
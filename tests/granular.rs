@@ -0,0 +1,203 @@
+mod common;
+
+use common::*;
+use condemus::*;
+
+fn mode(interval: f64, smoothing: SmoothingMode) -> GranulatingMode {
+    GranulatingMode::new(LoopSection::new(0.0, 0.1), interval, 1.0, smoothing)
+}
+
+fn render_stereo(
+    sampler: &mut dyn SamplerState,
+    len: usize,
+    block_len: usize,
+) -> (Vec<f64>, Vec<f64>) {
+    let mut left = vec![0.0; len];
+    let mut right = vec![0.0; len];
+
+    for (l, r) in left.chunks_mut(block_len).zip(right.chunks_mut(block_len)) {
+        sampler.render(
+            AudioBufferSlice::new(l, RATE, &LinearResampler),
+            AudioBufferSlice::new(r, RATE, &LinearResampler),
+            1.0,
+        );
+    }
+
+    (left, right)
+}
+
+fn render(sampler: &mut dyn SamplerState, len: usize, block_len: usize) -> Vec<f64> {
+    render_stereo(sampler, len, block_len).0
+}
+
+#[test]
+fn overlapping_triangles_add_up_to_flat_output() {
+    let data = project(vec![1.0; 2000]);
+    let mut sampler = GranulatingSamplerState::new(data, 0, mode(0.05, SmoothingMode::Triangle));
+    let out = render(&mut sampler, 4000, 256);
+
+    // once the second granule started, every frame is covered by two complementary windows
+    for x in &out[400..] {
+        assert!((x - 1.0).abs() < 1e-9, "expected flat output, got {}", x);
+    }
+}
+
+#[test]
+fn output_is_continuous() {
+    let data = project(sine(50.0, 2000));
+    let mut sampler = GranulatingSamplerState::new(data, 0, mode(0.03, SmoothingMode::Cosine(0.5)));
+    let out = render(&mut sampler, 8000, 100);
+
+    assert!(out.iter().any(|x| x.abs() > 0.1), "output is silent");
+
+    for (i, pair) in out.windows(2).enumerate() {
+        let jump = (pair[1] - pair[0]).abs();
+        assert!(jump < 0.1, "discontinuity of {} at frame {}", jump, i + 1);
+    }
+}
+
+#[test]
+fn output_does_not_depend_on_block_size() {
+    let audio = sine(330.0, 2000);
+
+    let mut whole = GranulatingSamplerState::new(
+        project(audio.clone()),
+        0,
+        mode(0.037, SmoothingMode::Linear(0.2)),
+    );
+    let mut split =
+        GranulatingSamplerState::new(project(audio), 0, mode(0.037, SmoothingMode::Linear(0.2)));
+
+    let whole = render(&mut whole, 6000, 6000);
+    let split = render(&mut split, 6000, 37);
+
+    for (a, b) in whole.iter().zip(&split) {
+        assert!((a - b).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn released_sampler_lets_granules_finish() {
+    let data = project(vec![1.0; 2000]);
+    let mut sampler = GranulatingSamplerState::new(data, 0, mode(0.05, SmoothingMode::Triangle));
+
+    render(&mut sampler, 1000, 256);
+    sampler.release();
+    assert!(!sampler.finished());
+
+    render(&mut sampler, 1000, 256);
+    assert!(sampler.finished());
+    assert!(render(&mut sampler, 100, 100).iter().all(|x| *x == 0.0));
+}
+
+fn textured() -> GranulatingMode {
+    GranulatingMode {
+        position_jitter: 0.02,
+        pitch_spread: 3.0,
+        pan_spread: 1.0,
+        interval_jitter: 0.5,
+        seed: 7,
+        ..mode(0.02, SmoothingMode::Cosine(0.5))
+    }
+}
+
+#[test]
+fn randomness_is_reproducible_from_the_seed() {
+    let audio = sine(220.0, 4000);
+
+    let mut a = GranulatingSamplerState::new(project(audio.clone()), 0, textured());
+    let mut b = GranulatingSamplerState::new(project(audio.clone()), 0, textured());
+    let mut c = GranulatingSamplerState::new(
+        project(audio),
+        0,
+        GranulatingMode {
+            seed: 8,
+            ..textured()
+        },
+    );
+
+    let a = render_stereo(&mut a, 4000, 256);
+    let b = render_stereo(&mut b, 4000, 100);
+    let c = render_stereo(&mut c, 4000, 256);
+
+    for (x, y) in a.0.iter().zip(&b.0).chain(a.1.iter().zip(&b.1)) {
+        assert!((x - y).abs() < 1e-9);
+    }
+
+    assert!(a.0.iter().zip(&c.0).any(|(x, y)| (x - y).abs() > 1e-3));
+}
+
+#[test]
+fn pan_spread_widens_the_image() {
+    let mut sampler = GranulatingSamplerState::new(project(sine(220.0, 4000)), 0, textured());
+    let (left, right) = render_stereo(&mut sampler, 4000, 256);
+
+    assert!(left.iter().zip(&right).any(|(l, r)| (l - r).abs() > 0.1));
+}
+
+#[test]
+fn scanning_moves_the_segment_through_the_sample() {
+    // the first half of the sample is silent, the second half is not
+    let mut audio = vec![0.0; 4000];
+    audio.extend(vec![1.0; 4000]);
+
+    let scanning = GranulatingMode {
+        scan_speed: 1.0,
+        ..mode(0.05, SmoothingMode::Triangle)
+    };
+    let mut sampler = GranulatingSamplerState::new(project(audio), 0, scanning);
+    let out = render(&mut sampler, 8000, 256);
+
+    assert!(out[..3000].iter().all(|x| x.abs() < 1e-9));
+    assert!(out[5000..7000].iter().all(|x| (x - 1.0).abs() < 1e-9));
+}
+
+#[test]
+fn next_loop_crossfades_into_the_next_stage() {
+    // the first stage plays a region at 1.0, the second one a region at 0.5
+    let mut audio = vec![1.0; 1600];
+    audio.extend(vec![0.5; 1600]);
+
+    let stages = GranulatingStages {
+        stages: vec![
+            mode(0.05, SmoothingMode::Triangle),
+            GranulatingMode::new(
+                LoopSection::new(0.25, 0.35),
+                0.05,
+                1.0,
+                SmoothingMode::Triangle,
+            ),
+        ],
+        crossfade: 0.1,
+    };
+
+    let mut sampler = GranulatingSamplerState::new(project(audio), 0, stages);
+    let before = render(&mut sampler, 2000, 256);
+
+    assert!(sampler.next_loop());
+    let after = render(&mut sampler, 4000, 256);
+
+    assert!((before[1999] - 1.0).abs() < 1e-9);
+    assert!(after[2000..].iter().all(|x| (x - 0.5).abs() < 1e-9));
+
+    for pair in after.windows(2) {
+        assert!((pair[1] - pair[0]).abs() < 0.01);
+    }
+
+    assert!(!sampler.next_loop());
+}
+
+#[test]
+fn instruments_saved_with_a_single_mode_still_load() {
+    let mode: InstrumentMode = serde_json::from_str(
+        r#"{"Granulating":{"segment":{"from":0.1,"to":0.3},"interval":0.05,"gain":0.8,"smoothing":"Triangle"}}"#,
+    )
+    .unwrap();
+
+    let InstrumentMode::Granulating(stages) = mode else {
+        panic!("not a granulating mode");
+    };
+    assert_eq!(stages.stages.len(), 1);
+    assert_eq!(stages.stages[0].interval, 0.05);
+    assert_eq!(stages.crossfade, 0.0);
+}
@@ -0,0 +1,172 @@
+mod common;
+
+use common::*;
+use condemus::*;
+
+/// Writes interleaved values into a WAV file of the given format.
+fn wav<T: hound::Sample + Copy>(
+    name: &str,
+    values: &[T],
+    channels: u16,
+    bits_per_sample: u16,
+    sample_format: hound::SampleFormat,
+) -> TempFile {
+    let file = TempFile::new(name);
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate: RATE as u32,
+        bits_per_sample,
+        sample_format,
+    };
+    let mut writer = hound::WavWriter::create(&file.0, spec).unwrap();
+
+    for value in values {
+        writer.write_sample(*value).unwrap();
+    }
+
+    writer.finalize().unwrap();
+    file
+}
+
+#[test]
+fn integer_wav_keeps_its_bit_depth() {
+    let values = [0, 1000, -32768, 32767, -1, 12345];
+    let file = wav("int16.wav", &values, 2, 16, hound::SampleFormat::Int);
+    let imported = Sample::import(&file.0, &ImportOptions::default()).unwrap();
+
+    assert_eq!(imported.bits_per_sample, Some(16));
+    assert_eq!(imported.channels, 2);
+    assert_eq!(imported.sample.audio, SampleData::I16(values.to_vec()));
+    assert_eq!(imported.sample.frames(), 3);
+
+    let values = [0, -8_388_608, 8_388_607, 4321];
+    let file = wav("int24.wav", &values, 1, 24, hound::SampleFormat::Int);
+    let sample = Sample::from_file(&file.0).unwrap();
+
+    assert_eq!(sample.audio, SampleData::I24(values.to_vec()));
+}
+
+#[test]
+fn integer_wav_downmixes_at_its_bit_depth() {
+    let values: [i16; 6] = [100, 301, -32768, -32768, 30000, 30000];
+    let file = wav("downmix.wav", &values, 2, 16, hound::SampleFormat::Int);
+
+    let import = |downmix| {
+        let options = ImportOptions {
+            downmix: Some(downmix),
+        };
+        Sample::import(&file.0, &options).unwrap().sample.audio
+    };
+
+    assert_eq!(
+        import(Downmix::Average),
+        SampleData::I16(vec![201, -32768, 30000])
+    );
+    assert_eq!(
+        import(Downmix::Sum),
+        SampleData::I16(vec![401, -32768, 32767])
+    );
+    assert_eq!(
+        import(Downmix::Channel(1)),
+        SampleData::I16(vec![301, -32768, 30000])
+    );
+}
+
+#[test]
+fn float_wav_is_kept_as_floats() {
+    let values = [0.0_f32, 0.5, -0.25, 1.0];
+    let file = wav("float.wav", &values, 1, 32, hound::SampleFormat::Float);
+    let imported = Sample::import(&file.0, &ImportOptions::default()).unwrap();
+
+    assert_eq!(imported.bits_per_sample, Some(32));
+    assert_eq!(imported.sample.audio, SampleData::F32(values.to_vec()));
+}
+
+#[test]
+fn other_integer_depths_are_read_as_floats() {
+    let values: [i8; 4] = [0, 127, -127, 64];
+    let file = wav("int8.wav", &values, 1, 8, hound::SampleFormat::Int);
+    let imported = Sample::import(&file.0, &ImportOptions::default()).unwrap();
+
+    assert_eq!(imported.bits_per_sample, Some(8));
+    assert_eq!(
+        imported.sample.audio,
+        SampleData::F32(vec![0.0, 1.0, -1.0, 64.0 / 127.0])
+    );
+}
+
+#[test]
+fn float_wav_downmixes_into_one_channel() {
+    let values = [0.5_f32, 0.25, -0.5, 0.75, 0.75, 0.5];
+    let file = wav(
+        "float-downmix.wav",
+        &values,
+        2,
+        32,
+        hound::SampleFormat::Float,
+    );
+
+    let import = |downmix| {
+        let options = ImportOptions {
+            downmix: Some(downmix),
+        };
+        let imported = Sample::import(&file.0, &options).unwrap();
+
+        // the file's channels are still reported, while the sample has only one
+        assert_eq!(imported.channels, 2);
+        assert_eq!(imported.sample.channels, 1);
+        imported.sample.audio
+    };
+
+    assert_eq!(
+        import(Downmix::Average),
+        SampleData::F32(vec![0.375, 0.125, 0.625])
+    );
+    assert_eq!(
+        import(Downmix::Sum),
+        SampleData::F32(vec![0.75, 0.25, 1.25])
+    );
+    assert_eq!(
+        import(Downmix::Channel(0)),
+        SampleData::F32(vec![0.5, -0.5, 0.75])
+    );
+    assert_eq!(
+        import(Downmix::Channel(1)),
+        SampleData::F32(vec![0.25, 0.75, 0.5])
+    );
+}
+
+#[test]
+fn keeping_a_channel_the_file_does_not_have_fails() {
+    let file = wav(
+        "no-such-channel.wav",
+        &[0.5_f32, 0.25],
+        2,
+        32,
+        hound::SampleFormat::Float,
+    );
+    let options = ImportOptions {
+        downmix: Some(Downmix::Channel(2)),
+    };
+
+    assert!(matches!(
+        Sample::import(&file.0, &options),
+        Err(ImportError::NoSuchChannel {
+            channel: 2,
+            channels: 2
+        })
+    ));
+}
+
+#[test]
+fn duration_is_reported_in_seconds() {
+    let values = vec![0_i16; 2 * RATE as usize * 3 / 2];
+    let file = wav("duration.wav", &values, 2, 16, hound::SampleFormat::Int);
+
+    for downmix in [None, Some(Downmix::Average)] {
+        let imported = Sample::import(&file.0, &ImportOptions { downmix }).unwrap();
+
+        assert_eq!(imported.duration, 1.5);
+        assert_eq!(imported.sample.duration(), 1.5);
+    }
+}
@@ -0,0 +1,380 @@
+mod common;
+
+use common::*;
+use condemus::*;
+use proptest::prelude::*;
+
+fn section() -> impl Strategy<Value = LoopSection> {
+    (0.0..10.0, 0.01..10.0).prop_map(|(from, len)| LoopSection::new(from, from + len))
+}
+
+fn looping() -> impl Strategy<Value = LoopDef> {
+    (section(), 0..3).prop_map(|(section, which)| match which {
+        0 => LoopDef::Forward(section),
+        1 => LoopDef::Backward(section),
+        _ => LoopDef::PingPong(section),
+    })
+}
+
+fn position(at: f64, reversing: bool) -> Position {
+    Position { at, reversing }
+}
+
+proptest! {
+    #[test]
+    fn stops_lie_ahead(def in looping(), at in 0.0..30.0, reversing: bool) {
+        if let Some(stop) = def.next_stop(position(at, reversing)) {
+            if reversing {
+                prop_assert!(stop <= at);
+            } else {
+                prop_assert!(stop >= at);
+            }
+        }
+    }
+
+    #[test]
+    fn loops_behind_are_not_stopped_at(def in looping(), past in 0.001..10.0) {
+        let section = def.section().unwrap();
+        prop_assert!(def.next_stop(position(section.to + past, false)).is_none());
+    }
+
+    #[test]
+    fn reversing_below_a_loop_restarts_from_the_beginning(
+        def in looping(),
+        below in 0.001..1.0_f64,
+    ) {
+        let section = def.section().unwrap();
+        let at = (section.from - below).max(0.0);
+        prop_assume!(at < section.from);
+
+        prop_assert_eq!(def.next_stop(position(at, true)), Some(0.0));
+
+        let start = def.next_start(position(0.0, true)).unwrap();
+        prop_assert_eq!(start.at, 0.0);
+        prop_assert!(!start.reversing);
+    }
+
+    #[test]
+    fn playback_inside_a_loop_stays_inside(
+        def in looping(),
+        offset in 0.0..1.0,
+        reversing: bool,
+    ) {
+        let section = def.section().unwrap();
+        let mut position = position(section.from + offset * section.len(), reversing);
+
+        for _ in 0..10 {
+            let stop = def.next_stop(position).unwrap();
+            prop_assert!(stop == section.from || stop == section.to);
+
+            position = def.next_start(Position { at: stop, ..position }).unwrap();
+            prop_assert!(position.at == section.from || position.at == section.to);
+        }
+    }
+
+    #[test]
+    fn loops_go_around_in_their_direction(def in looping(), reversing: bool) {
+        let section = def.section().unwrap();
+        let stop = if reversing { section.from } else { section.to };
+        let start = def.next_start(position(stop, reversing)).unwrap();
+
+        match def {
+            LoopDef::Forward(_) => {
+                prop_assert_eq!(start.reversing, reversing);
+                prop_assert_eq!(start.at, if reversing { section.to } else { section.from });
+            }
+            LoopDef::Backward(_) => {
+                prop_assert!(start.reversing);
+                prop_assert_eq!(start.at, section.to);
+            }
+            _ => {
+                prop_assert_eq!(start.reversing, !reversing);
+                prop_assert_eq!(start.at, stop);
+            }
+        }
+    }
+
+    #[test]
+    fn counted_loops_never_go_back_to_the_start(
+        first in (0.1..0.25, 0.05..0.2, 0..3_u32),
+        second in (0.5..0.7, 0.05..0.2, 0..3_u32),
+        kinds in (0..3, 0..3),
+    ) {
+        let def = |(from, len, repeats): (f64, f64, u32), kind| {
+            let section = counted(from, from + len, repeats);
+            match kind {
+                0 => LoopDef::Forward(section),
+                1 => LoopDef::Backward(section),
+                _ => LoopDef::PingPong(section),
+            }
+        };
+        let mut sampler = chained(vec![def(first, kinds.0), def(second, kinds.1)]);
+        let out = render(&mut sampler, 4.0);
+
+        // once playback reached the first loop, it only ever goes through the loops and on, up
+        // to the last frame, which fades into the silence past the end of the sample
+        let entered = out.iter().position(|x| *x >= first.0).unwrap();
+        let end = out.iter().rposition(|x| *x != 0.0).unwrap();
+        prop_assert!(out[entered..end].iter().all(|x| *x > first.0 - 0.01));
+        prop_assert!(out.iter().any(|x| *x > 0.98));
+        prop_assert!(sampler.finished());
+    }
+
+    #[test]
+    fn bounce_reflects_overshoot_off_the_wall(
+        wall in 0.0..10.0,
+        past in 0.0..1.0,
+        reversing: bool,
+    ) {
+        let overshot = position(wall, reversing).after(past);
+        let bounced = overshot.bounce(past);
+
+        prop_assert!((bounced.at - position(wall, reversing).after(-past).at).abs() < 1e-9);
+        prop_assert_eq!(bounced.reversing, !reversing);
+    }
+
+    #[test]
+    fn bouncing_twice_goes_back(at in 0.0..10.0, past in 0.0..1.0, reversing: bool) {
+        let twice = position(at, reversing).bounce(past).bounce(past);
+
+        prop_assert!((twice.at - at).abs() < 1e-9);
+        prop_assert_eq!(twice.reversing, reversing);
+    }
+}
+
+#[test]
+fn no_loop_has_no_stops() {
+    assert!(LoopDef::None.next_stop(position(1.0, false)).is_none());
+    assert!(LoopDef::None.next_start(position(1.0, false)).is_err());
+}
+
+fn sampler(def: LoopDef) -> BasicSamplerState {
+    chained(vec![def])
+}
+
+/// A sampler that goes through each loop after the other.
+fn chained(loops: Vec<LoopDef>) -> BasicSamplerState {
+    started(loops, NoteStart::default())
+}
+
+fn started(loops: Vec<LoopDef>, start: NoteStart) -> BasicSamplerState {
+    BasicSamplerState::new(
+        project(time_ramp(RATE as usize)),
+        0,
+        BasicMode { start: 0.0, loops },
+        start,
+    )
+}
+
+fn from(offset: Option<SampleOffset>, reverse: bool) -> NoteStart {
+    NoteStart { offset, reverse }
+}
+
+fn play(def: LoopDef, secs: f64) -> (Vec<f64>, BasicSamplerState) {
+    let mut sampler = sampler(def);
+    let left = render(&mut sampler, secs);
+
+    (left, sampler)
+}
+
+fn render(sampler: &mut BasicSamplerState, secs: f64) -> Vec<f64> {
+    let mut left = vec![0.0; frame(secs)];
+    let mut right = vec![0.0; frame(secs)];
+
+    for (l, r) in left.chunks_mut(64).zip(right.chunks_mut(64)) {
+        sampler.render(
+            AudioBufferSlice::new(l, RATE, &LinearResampler),
+            AudioBufferSlice::new(r, RATE, &LinearResampler),
+            1.0,
+        );
+    }
+
+    left
+}
+
+fn counted(from: f64, to: f64, repeats: u32) -> LoopSection {
+    LoopSection {
+        repeats: Some(repeats),
+        ..LoopSection::new(from, to)
+    }
+}
+
+fn frame(secs: f64) -> usize {
+    (secs * RATE).round() as usize
+}
+
+fn assert_near(out: &[f64], secs: f64, expected: f64) {
+    let at = out[frame(secs)];
+    assert!(
+        (at - expected).abs() < 0.002,
+        "expected {} at {}s, got {}",
+        expected,
+        secs,
+        at
+    );
+}
+
+#[test]
+fn counted_forward_loop_moves_on_by_itself() {
+    let (out, sampler) = play(LoopDef::Forward(counted(0.25, 0.5, 2)), 2.0);
+
+    // 0 to 0.5, twice more from 0.25, then on to the end
+    assert_near(&out, 0.6, 0.35);
+    assert_near(&out, 0.85, 0.35);
+    assert_near(&out, 1.1, 0.6);
+    assert_near(&out, 1.49, 0.99);
+    assert!(out[frame(1.51)..].iter().all(|x| *x == 0.0));
+    assert!(sampler.finished());
+}
+
+#[test]
+fn backward_loop_plays_its_section_in_reverse() {
+    let (out, sampler) = play(LoopDef::Backward(counted(0.2, 0.4, 1)), 1.5);
+
+    // up to 0.4, down to 0.2 twice, then on from 0.4 to the end
+    assert_near(&out, 0.3, 0.3);
+    assert_near(&out, 0.5, 0.3);
+    assert_near(&out, 0.7, 0.3);
+    assert_near(&out, 0.9, 0.5);
+    assert_near(&out, 1.39, 0.99);
+    assert!(out[frame(1.41)..].iter().all(|x| *x == 0.0));
+    assert!(sampler.finished());
+}
+
+#[test]
+fn released_backward_loop_plays_out_then_carries_on_past_its_end() {
+    let mut sampler = sampler(LoopDef::Backward(LoopSection::new(0.2, 0.4)));
+    let held = render(&mut sampler, 0.5);
+    assert_near(&held, 0.45, 0.35);
+
+    // released on the way down, at 0.3
+    sampler.release();
+    let out = render(&mut sampler, 1.0);
+
+    assert_near(&out, 0.05, 0.25);
+    assert_near(&out, 0.2, 0.5);
+    assert_near(&out, 0.69, 0.99);
+    assert!(out[frame(0.71)..].iter().all(|x| *x == 0.0));
+    assert!(sampler.finished());
+}
+
+#[test]
+fn counted_ping_pong_loop_bounces_once_more_on_its_way_out() {
+    let mut sampler = chained(vec![
+        LoopDef::PingPong(counted(0.2, 0.4, 1)),
+        LoopDef::Forward(LoopSection::new(0.6, 0.8)),
+    ]);
+    let out = render(&mut sampler, 1.6);
+
+    // up to 0.4, down to 0.2, then up again and on into the next loop, without restarting
+    assert_near(&out, 0.3, 0.3);
+    assert_near(&out, 0.5, 0.3);
+    assert_near(&out, 0.7, 0.3);
+    assert_near(&out, 1.0, 0.6);
+    assert_near(&out, 1.3, 0.7);
+    assert_near(&out, 1.5, 0.7);
+    assert!(out[frame(0.4)..].iter().all(|x| *x > 0.19));
+}
+
+#[test]
+fn released_ping_pong_loop_turns_around_on_its_way_down() {
+    let mut sampler = sampler(LoopDef::PingPong(LoopSection::new(0.2, 0.4)));
+    let held = render(&mut sampler, 0.5);
+    assert_near(&held, 0.45, 0.35);
+
+    // released on the way down, at 0.3
+    sampler.release();
+    let out = render(&mut sampler, 1.0);
+
+    assert_near(&out, 0.05, 0.35);
+    assert_near(&out, 0.6, 0.9);
+    assert!(out[..frame(0.69)].iter().all(|x| *x > 0.29));
+    assert!(out[frame(0.71)..].iter().all(|x| *x == 0.0));
+    assert!(sampler.finished());
+}
+
+#[test]
+fn uncounted_loop_keeps_going() {
+    let (out, sampler) = play(LoopDef::PingPong(LoopSection::new(0.2, 0.4)), 3.0);
+
+    assert_near(&out, 2.9, 0.3);
+    assert!(!sampler.finished());
+}
+
+#[test]
+fn note_starts_at_an_offset_in_seconds() {
+    let mut sampler = started(vec![], from(Some(SampleOffset::Seconds(0.3)), false));
+    let out = render(&mut sampler, 1.0);
+
+    assert_near(&out, 0.0, 0.3);
+    assert_near(&out, 0.5, 0.8);
+    assert!(out[frame(0.71)..].iter().all(|x| *x == 0.0));
+    assert!(sampler.finished());
+}
+
+#[test]
+fn note_starts_at_a_fraction_of_the_sample() {
+    let mut sampler = started(vec![], from(Some(SampleOffset::Fraction(0.25)), false));
+    let out = render(&mut sampler, 1.0);
+
+    assert_near(&out, 0.0, 0.25);
+    assert_near(&out, 0.5, 0.75);
+    assert!(out[frame(0.76)..].iter().all(|x| *x == 0.0));
+}
+
+#[test]
+fn reversed_note_plays_from_the_end_to_the_start() {
+    let mut sampler = started(vec![], from(None, true));
+    let out = render(&mut sampler, 1.5);
+
+    assert_near(&out, 0.1, 0.9);
+    assert_near(&out, 0.7, 0.3);
+    assert!(out[frame(1.0)..].iter().all(|x| *x == 0.0));
+    assert!(sampler.finished());
+}
+
+#[test]
+fn reversed_note_from_an_offset_plays_down_to_the_start() {
+    let mut sampler = started(vec![], from(Some(SampleOffset::Fraction(0.6)), true));
+    let out = render(&mut sampler, 1.0);
+
+    assert_near(&out, 0.0, 0.6);
+    assert_near(&out, 0.4, 0.2);
+    assert!(out[frame(0.6)..].iter().all(|x| *x == 0.0));
+    assert!(sampler.finished());
+}
+
+#[test]
+fn reversed_note_below_a_loop_ends_at_the_start() {
+    let section = LoopSection::new(0.5, 0.7);
+
+    for def in [
+        LoopDef::Forward(section),
+        LoopDef::Backward(section),
+        LoopDef::PingPong(section),
+    ] {
+        let mut sampler = started(vec![def], from(Some(SampleOffset::Seconds(0.2)), true));
+        let out = render(&mut sampler, 1.0);
+
+        // rather than turning around at the start and playing on into the loop
+        assert_near(&out, 0.1, 0.1);
+        assert!(out[frame(0.2)..].iter().all(|x| *x == 0.0));
+        assert!(sampler.finished());
+    }
+}
+
+#[test]
+fn reversed_note_goes_around_a_loop_on_its_way_down() {
+    let mut sampler = started(
+        vec![LoopDef::Forward(counted(0.2, 0.4, 1))],
+        from(None, true),
+    );
+    let out = render(&mut sampler, 1.5);
+
+    // down to 0.2, from 0.4 down to it once more, then on to the start
+    assert_near(&out, 0.5, 0.5);
+    assert_near(&out, 0.9, 0.3);
+    assert_near(&out, 1.1, 0.1);
+    assert!(out[frame(1.2)..].iter().all(|x| *x == 0.0));
+    assert!(sampler.finished());
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use common::edits::*;
+use condemus::*;
+
+#[test]
+fn trim_keeps_the_frames_between_its_points() {
+    check(|sample| sample.trim(0.3, 0.9), &ramp(3..9));
+    check(|sample| sample.trim(1.2, 5.0), &ramp(12..16));
+    check(|sample| sample.trim(0.9, 0.3), &[]);
+}
+
+#[test]
+fn fades_reach_silence_at_the_edges() {
+    let mut expected = ramp(0..16);
+    for (i, gain) in [0.0, 0.25, 0.5, 0.75].into_iter().enumerate() {
+        expected[i] *= gain;
+    }
+    check(|sample| sample.fade_in(0.4), &expected);
+
+    let mut expected = ramp(0..16);
+    for (i, gain) in [0.75, 0.5, 0.25, 0.0].into_iter().enumerate() {
+        expected[12 + i] *= gain;
+    }
+    check(|sample| sample.fade_out(0.4), &expected);
+
+    // a fade longer than the sample covers all of it
+    let expected: Vec<f64> = ramp(0..16).iter().map(|x| x * x * 2.0).collect();
+    check(|sample| sample.fade_in(3.0), &expected);
+}
+
+#[test]
+fn silence_is_inserted_where_asked() {
+    let expected = [ramp(0..5), vec![0.0; 3], ramp(5..16)].concat();
+    check(|sample| sample.insert_silence(0.5, 0.3), &expected);
+
+    let expected = [ramp(0..16), vec![0.0; 2]].concat();
+    check(|sample| sample.insert_silence(4.0, 0.2), &expected);
+}
+
+#[test]
+fn resampling_keeps_the_duration() {
+    // every other frame falls halfway between two of the original
+    let mut expected: Vec<f64> = (0..32).map(|i| i as f64 / 64.0).collect();
+    expected[31] = 15.0 / 64.0; // halfway into the silence past the end
+
+    check(
+        |sample| {
+            let resampled = sample.resample(TENTHS * 2.0, &LinearResampler);
+            assert_eq!(resampled.baserate, TENTHS * 2.0);
+            assert_eq!(resampled.duration(), sample.duration());
+            resampled
+        },
+        &expected,
+    );
+}
+
+#[test]
+fn mono_audio_mixes_into_every_channel() {
+    let other = Sample::mono(vec![0.25; 4], TENTHS);
+
+    let (mut left, mut right) = (ramp(0..16), negated(&ramp(0..16)));
+    for x in left[2..6].iter_mut().chain(&mut right[2..6]) {
+        *x += 0.125;
+    }
+    check_stereo(
+        |sample| sample.mix_in(&other, 0.2, 0.5, &LinearResampler),
+        &left,
+        &right,
+    );
+
+    // the sample grows to fit what goes past its end
+    let mut left = [ramp(0..16), vec![0.0; 2]].concat();
+    let mut right = negated(&left);
+    for x in left[14..].iter_mut().chain(&mut right[14..]) {
+        *x += 0.125;
+    }
+    check_stereo(
+        |sample| sample.mix_in(&other, 1.4, 0.5, &LinearResampler),
+        &left,
+        &right,
+    );
+}
+
+#[test]
+fn other_rates_are_resampled_before_mixing() {
+    // twice the rate, so every other frame lands on one of the sample
+    let other = Sample::mono(vec![0.25; 8], TENTHS * 2.0);
+
+    let (mut left, mut right) = (ramp(0..16), negated(&ramp(0..16)));
+    for x in left[3..7].iter_mut().chain(&mut right[3..7]) {
+        *x += 0.25;
+    }
+    check_stereo(
+        |sample| sample.mix_in(&other, 0.3, 1.0, &LinearResampler),
+        &left,
+        &right,
+    );
+}
+
+#[test]
+fn paste_pushes_the_rest_later() {
+    let other = Sample::mono(vec![0.25; 3], TENTHS);
+    let pasted = |channel: Vec<f64>| [&channel[..5], &[0.25; 3], &channel[5..]].concat();
+
+    check_stereo(
+        |sample| sample.paste(&other, 0.5, &LinearResampler),
+        &pasted(ramp(0..16)),
+        &pasted(negated(&ramp(0..16))),
+    );
+}
+
+#[test]
+fn stereo_audio_mixes_channel_by_channel() {
+    let other = Sample {
+        audio: vec![0.25, -0.25, 0.25, -0.25].into(),
+        baserate: TENTHS,
+        channels: 2,
+        layout: ChannelLayout::Interleaved,
+    };
+
+    let (mut left, mut right) = (ramp(0..16), negated(&ramp(0..16)));
+    left[1..3].iter_mut().for_each(|x| *x += 0.25);
+    right[1..3].iter_mut().for_each(|x| *x -= 0.25);
+    check_stereo(
+        |sample| sample.mix_in(&other, 0.1, 1.0, &LinearResampler),
+        &left,
+        &right,
+    );
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use common::*;
+use condemus::*;
+use std::sync::Arc;
+
+/// A stereo 16-bit WAV file of a few blocks of cache, along with the same audio in memory.
+fn streamed_and_loaded(name: &str) -> (TempFile, Sample, Sample) {
+    let file = TempFile::new(name);
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: RATE as u32,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(&file.0, spec).unwrap();
+
+    for (l, r) in sine(440.0, 100_000).iter().zip(sine(130.0, 100_000)) {
+        writer.write_sample((l * 30000.0) as i16).unwrap();
+        writer.write_sample((r * 30000.0) as i16).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let streamed = Sample::stream_wav(&file.0).unwrap();
+    let loaded = Sample::from_file(&file.0).unwrap();
+    (file, streamed, loaded)
+}
+
+fn mix(sample: &Sample, start: f64, speed: f64, shaped: bool) -> Vec<f64> {
+    let mut out = vec![0.0; 3000];
+
+    for (c, channel) in sample.stereo().into_iter().enumerate() {
+        let mut slice = AudioBufferSlice::new(&mut out, RATE, &CubicResampler);
+
+        if shaped {
+            slice.mix_shaped(channel, sample.baserate, start, speed, |secs| {
+                secs * (c + 1) as f64
+            });
+        } else {
+            slice.mix(channel, sample.baserate, start, speed, 0.5 * (c + 1) as f64);
+        }
+    }
+
+    out
+}
+
+#[test]
+fn streamed_audio_mixes_like_audio_in_memory() {
+    let (_file, streamed, loaded) = streamed_and_loaded("mix.wav");
+    assert!(matches!(streamed.audio, SampleData::Streamed(_)));
+
+    // across the blocks of the cache, both ways, and over both ends of the sample
+    for (start, speed) in [
+        (4.0, 1.37),
+        (8.5, -2.2),
+        (0.1, -0.9),
+        (12.3, 1.5),
+        (20.0, 1.0),
+    ] {
+        for shaped in [false, true] {
+            let streamed = mix(&streamed, start, speed, shaped);
+            let loaded = mix(&loaded, start, speed, shaped);
+
+            assert!(loaded.iter().any(|x| x.abs() > 0.1) || start > 12.5);
+
+            for (i, (a, b)) in streamed.iter().zip(&loaded).enumerate() {
+                assert!(
+                    (a - b).abs() < 1e-9,
+                    "from {} at speed {}: differ at frame {}",
+                    start,
+                    speed,
+                    i
+                );
+            }
+        }
+    }
+}
+
+fn streamed_audio(sample: &Sample) -> &StreamedAudio {
+    match &sample.audio {
+        SampleData::Streamed(audio) => audio,
+        _ => panic!("sample is not streamed"),
+    }
+}
+
+#[test]
+fn sequential_reads_find_the_next_block_read_ahead() {
+    let (_file, streamed, _) = streamed_and_loaded("ahead.wav");
+    let audio = streamed_audio(&streamed);
+
+    // only the first block is waited for, and every block after it is read ahead
+    for i in 0..audio.len() {
+        audio.get(i);
+    }
+    assert_eq!(audio.misses(), 1);
+
+    // backwards, the second block tells which way playback moves
+    let (_file, streamed, _) = streamed_and_loaded("behind.wav");
+    let audio = streamed_audio(&streamed);
+
+    for i in (0..audio.len()).rev() {
+        audio.get(i);
+    }
+    assert_eq!(audio.misses(), 2);
+}
+
+/// Plays a sample through a sampler, from a start and through loops.
+fn play(sample: Sample, start: NoteStart, loops: Vec<LoopDef>) -> Vec<f64> {
+    let data = Arc::new(Project {
+        patterns: vec![],
+        samples: vec![sample],
+        instruments: vec![],
+        tracks: vec![],
+    });
+    let mut sampler = BasicSamplerState::new(data, 0, BasicMode { start: 0.0, loops }, start);
+
+    let mut left = vec![0.0; RATE as usize];
+    let mut right = vec![0.0; RATE as usize];
+
+    for (l, r) in left.chunks_mut(100).zip(right.chunks_mut(100)) {
+        sampler.render(
+            AudioBufferSlice::new(l, RATE, &CubicResampler),
+            AudioBufferSlice::new(r, RATE, &CubicResampler),
+            1.0,
+        );
+    }
+
+    left.extend(right);
+    left
+}
+
+#[test]
+fn streamed_sample_plays_like_one_in_memory() {
+    let (_file, streamed, loaded) = streamed_and_loaded("play.wav");
+    let from = |secs| NoteStart {
+        offset: Some(SampleOffset::Seconds(secs)),
+        reverse: false,
+    };
+
+    // the loops and the reversed note go back and forth over the boundary between two blocks,
+    // at 8.192 seconds
+    let cases = [
+        (
+            from(8.0),
+            vec![LoopDef::Forward(LoopSection::new(8.1, 8.3))],
+        ),
+        (
+            from(8.0),
+            vec![LoopDef::PingPong(LoopSection::new(8.15, 8.25))],
+        ),
+        (
+            NoteStart {
+                reverse: true,
+                ..from(8.6)
+            },
+            vec![],
+        ),
+    ];
+
+    for (start, loops) in cases {
+        let streamed = play(streamed.clone(), start, loops.clone());
+        let loaded = play(loaded.clone(), start, loops);
+
+        assert!(loaded.iter().any(|x| x.abs() > 0.1));
+
+        for (i, (a, b)) in streamed.iter().zip(&loaded).enumerate() {
+            assert!((a - b).abs() < 1e-9, "differ at frame {}", i);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use common::*;
+use condemus::*;
+
+fn sampler(audio: Vec<f64>, tempo: f64, pitch_rate: f64) -> TimeStretchSamplerState {
+    let mut sampler = TimeStretchSamplerState::new(
+        project(audio),
+        0,
+        TimeStretchMode {
+            start: 0.0,
+            source_bpm: 120.0,
+            grain: 0.04,
+            smoothing: SmoothingMode::Triangle,
+        },
+    );
+
+    sampler.set_tempo(tempo);
+    sampler.set_pitch_rate(pitch_rate);
+    sampler
+}
+
+/// Renders like a channel does, at a rate scaled by the pitch, until the sampler finishes.
+fn render(sampler: &mut TimeStretchSamplerState, pitch_rate: f64) -> Vec<f64> {
+    let mut out = vec![];
+
+    while !sampler.finished() && out.len() < RATE as usize * 10 {
+        let mut left = vec![0.0; 256];
+        let mut right = vec![0.0; 256];
+
+        sampler.render(
+            AudioBufferSlice::new(&mut left, RATE / pitch_rate, &LinearResampler),
+            AudioBufferSlice::new(&mut right, RATE / pitch_rate, &LinearResampler),
+            1.0,
+        );
+
+        out.extend(left);
+    }
+
+    out
+}
+
+/// Index of the last frame that is not silent.
+fn sound_end(out: &[f64]) -> usize {
+    out.iter().rposition(|x| x.abs() > 1e-9).unwrap_or(0)
+}
+
+#[test]
+fn length_follows_tempo_and_not_pitch() {
+    let audio = vec![1.0; RATE as usize];
+
+    let source = sound_end(&render(&mut sampler(audio.clone(), 120.0, 1.0), 1.0));
+    let faster = sound_end(&render(&mut sampler(audio.clone(), 240.0, 1.0), 1.0));
+    let pitched = sound_end(&render(&mut sampler(audio, 120.0, 2.0), 2.0));
+
+    let close = |a: usize, b: f64| (a as f64 - b).abs() < RATE * 0.05;
+
+    assert!(close(source, RATE), "played for {} frames", source);
+    assert!(close(faster, RATE / 2.0), "played for {} frames", faster);
+    assert!(close(pitched, RATE), "played for {} frames", pitched);
+}
+
+#[test]
+fn overlapping_grains_add_up_to_flat_output() {
+    let mut sampler = sampler(vec![1.0; RATE as usize * 2], 90.0, 1.5);
+    let out = render(&mut sampler, 1.5);
+
+    // the sample is read from its start, so only the grains that read past its end fade out
+    for x in &out[..RATE as usize] {
+        assert!((x - 1.0).abs() < 1e-9, "expected flat output, got {}", x);
+    }
+}
+
+/// The whole frequency between 100 and 1000 Hz that is loudest in the output.
+///
+/// Grains start at the phase of the audio they read, rather than carrying on from the one
+/// before, so counting zero crossings would find extra ones where they overlap.
+fn loudest_frequency(out: &[f64]) -> f64 {
+    // the power of the output at a frequency, by the Goertzel algorithm
+    let power = |freq: f64| {
+        let coeff = 2.0 * (freq * std::f64::consts::TAU / RATE).cos();
+        let (s1, s2) = out
+            .iter()
+            .fold((0.0, 0.0), |(s1, s2), x| (x + coeff * s1 - s2, s1));
+        s1 * s1 + s2 * s2 - coeff * s1 * s2
+    };
+
+    (100..=1000)
+        .map(f64::from)
+        .max_by(|a, b| power(*a).total_cmp(&power(*b)))
+        .unwrap()
+}
+
+#[test]
+fn pitch_follows_pitch_and_not_tempo() {
+    let audio = sine(410.0, RATE as usize * 4);
+
+    for (tempo, pitch_rate) in [
+        (60.0, 1.0),
+        (120.0, 1.0),
+        (180.0, 1.0),
+        (240.0, 1.0),
+        (90.0, 1.5),
+    ] {
+        let out = render(&mut sampler(audio.clone(), tempo, pitch_rate), pitch_rate);
+        let expected = 410.0 * pitch_rate;
+        let measured = loudest_frequency(&out[(0.1 * RATE) as usize..(0.9 * RATE) as usize]);
+
+        // grains start a hop of 0.02 seconds apart, at the phase of the audio they read, which
+        // moves the loudest frequency by up to half of their rate
+        assert!(
+            (measured - expected).abs() <= 25.0,
+            "expected {} Hz at {} BPM, got {} Hz",
+            expected,
+            tempo,
+            measured
+        );
+    }
+}